@@ -0,0 +1,161 @@
+/// FFT analysis of a sample stream into timestamped spectral columns
+
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use rustfft::{
+    Fft,
+    FftPlanner,
+    num_complex::Complex,
+};
+
+use super::settings;
+use super::spectrum::{self, Background, Blanker, Column, FftPlan, Notch};
+
+
+/// FFT chain built from the settings at hand: window, FFT plan, and the enabled clean-up stages
+pub (crate) struct Analyzer {
+    sample_rate:  u32,
+    iq:           bool,
+    plan:         FftPlan,
+    interval:     Duration,
+    window:       settings::FftWindow,
+    fft:          Arc<dyn Fft<f32>>,
+    buffer_raw:   Vec<Complex<f32>>, // buffer for unwindowed, unprocessed samples
+    buffer_proc:  Vec<Complex<f32>>, // buffer for windowed and FFT processed samples
+    fft_scratch:  Vec<Complex<f32>>, // scratch for fft processor
+    blanker:      Option<Blanker>,
+    notch:        Option<Notch>,
+    background:   Option<Background>,
+    // UTC time of first sample in stream and samples received since
+    anchor:       DateTime<Utc>,
+    sample_count: u64,
+}
+
+impl Analyzer {
+    /// Analyzer for a stream whose first sample was taken at `anchor`
+    pub (crate) fn new(set: &settings::Settings, anchor: DateTime<Utc>) -> Self {
+        Analyzer::with_timeline(set, anchor, 0)
+    }
+
+    /// Analyzer with the current `set`, continuing this one's stream
+    pub (crate) fn rebuild(&self, set: &settings::Settings) -> Self {
+        Analyzer::with_timeline(set, self.anchor, self.sample_count)
+    }
+
+    fn with_timeline(set: &settings::Settings, anchor: DateTime<Utc>, sample_count: u64) -> Self {
+        let sample_rate = set.audio.rate;
+        let plan = FftPlan::new(sample_rate, set.fft.resolution, set.fft.overlap, set.frame.dot_length);
+        let columns_per_frame = (set.frame.duration as u64 * sample_rate as u64 / plan.shift_size as u64) as u32;
+
+        let mut planner = FftPlanner::new();
+        Analyzer {
+            sample_rate,
+            iq:           set.audio.iq,
+            plan,
+            interval:     plan.column_interval(sample_rate),
+            window:       settings::FftWindow::new(plan.window_size as usize, &set.fft.window),
+            fft:          planner.plan_fft_forward(plan.fft_size as usize),
+            buffer_raw:   Vec::with_capacity(plan.window_size as usize),
+            buffer_proc:  Vec::with_capacity(plan.fft_size as usize),
+            fft_scratch:  vec![Complex::new(0., 0.); plan.fft_size as usize],
+            blanker:      match set.filter.blanker {
+                true  => Some(Blanker::new(set.filter.blanker_threshold)),
+                false => None,
+            },
+            notch:        match set.filter.notch {
+                true  => Some(Notch::new(set.filter.notch_threshold, columns_per_frame)),
+                false => None,
+            },
+            background:   match set.fft.subtract_background {
+                true  => Some(Background::new(set.fft.background_percentile, columns_per_frame)),
+                false => None,
+            },
+            anchor,
+            sample_count,
+        }
+    }
+
+    pub (crate) fn plan(&self) -> &FftPlan {
+        &self.plan
+    }
+
+    pub (crate) fn window_type(&self) -> settings::FftWindowType {
+        self.window.window_type
+    }
+
+    /// Feed captured samples, interleaved I/Q pairs in I/Q mode; `f` gets each finished column
+    pub (crate) fn process<F: FnMut(Column)>(&mut self, samples: &[f32], mut f: F) {
+        let FftPlan { window_size, fft_size, shift_size } = self.plan;
+
+        // combine I/Q channel pairs into complex samples; audio is real only
+        let d: Vec<Complex<f32>> = if self.iq {
+            samples.chunks_exact(2).map(|p| Complex::new(p[0], p[1])).collect()
+        } else {
+            samples.iter().map(|&s| Complex::new(s, 0.)).collect()
+        };
+
+        for s in d {
+            self.buffer_raw.push(s);
+            self.sample_count += 1;
+            if self.buffer_raw.len() < window_size as usize {
+                continue;
+            }
+
+            self.buffer_proc.clear();
+            self.buffer_proc.extend(
+                self.buffer_raw.iter()
+                    .zip(&self.window.window_func)
+                    .map(|x| *x.0 * *x.1));
+
+            // zero padding to increase FFT resolution
+            self.buffer_proc.extend(vec![Complex::new(0., 0.); self.plan.zero_padding() as usize]);
+
+            // FFT processing
+            self.fft.process_with_scratch(&mut self.buffer_proc, &mut self.fft_scratch);
+
+            if self.iq {
+                // complex input has distinct negative frequencies in the upper
+                //   half; rotate so bins run from -Nyquist up to Nyquist
+                self.buffer_proc.rotate_left(fft_size as usize / 2);
+            } else {
+                // discard all frequencies after Nyquist - powers of 2 always produce
+                //   an even number of samples
+                // N/2 for even number of input points (exactly Nyquist freq)
+                // (N-1)/2 for odd (last positive point)
+                self.buffer_proc.truncate(fft_size as usize / 2);
+            }
+
+            // normalize processed FFT samples, clean up and flatten the passband as enabled
+            let mut bins: Vec<f32> = self.buffer_proc.iter()
+                .map(|x| x.norm() / (fft_size as f32).sqrt())
+                .collect();
+            if let Some(b) = self.blanker.as_mut() {
+                b.blank(&mut bins);
+            }
+            if let Some(n) = self.notch.as_mut() {
+                n.notch(&mut bins);
+            }
+            if let Some(bg) = self.background.as_mut() {
+                bg.subtract(&mut bins);
+            }
+
+            // stamp with window center time
+            f(Column {
+                time:        spectrum::sample_time(
+                    self.anchor, self.sample_count - window_size as u64 / 2, self.sample_rate),
+                interval:    self.interval,
+                sample_rate: self.sample_rate,
+                iq:          self.iq,
+                total_bins:  bins.len(),
+                first_bin:   0,
+                bins,
+            });
+
+            // shift left window_size - overlap_samples and leave tail samples
+            self.buffer_raw.rotate_left(shift_size as usize);
+            self.buffer_raw.truncate((window_size - shift_size) as usize);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+/// Scales and header drawn around exported images, so grabs describe themselves
+///
+/// The waterfall is kept pixel for pixel; margins hold a header line with station, band and
+/// frame start, a frequency scale on the left (audio Hz, plus RF kHz with a dial frequency) that
+/// follows the sideband, and UTC time labels below.
+
+
+use image::{imageops, Rgb, RgbImage};
+
+use super::font;
+use super::render::{self, Grid};
+use super::settings;
+use super::spectrum;
+
+
+/// Colors of annotation text and margins
+const TEXT_COLOR: Rgb<u8> = Rgb([255, 255, 255]);
+const MARGIN_COLOR: Rgb<u8> = Rgb([0, 0, 0]);
+
+/// Space in pixels around text and between scale columns
+const PAD: u32 = 4;
+
+/// Length in pixels of scale ticks pointing away from the waterfall
+const TICK_LENGTH: u32 = 4;
+
+/// Size of header text, in multiples of the font size
+const HEADER_SCALE: u32 = 2;
+
+/// Least pixels between frequency labels
+const LABEL_SPACING: u32 = 30;
+
+/// What exported images are labeled with
+#[derive(Debug, Clone, PartialEq)]
+pub (crate) struct Labels {
+    pub freq_range: Vec<i32>,
+    pub radio:      settings::Radio,
+    pub station:    settings::Station,
+}
+
+impl Labels {
+    pub (crate) fn new(set: &settings::Settings) -> Self {
+        Labels {
+            freq_range: set.audio.freq_range.clone(),
+            radio:      set.radio.clone(),
+            station:    set.station.clone(),
+        }
+    }
+}
+
+/// Smallest step of 1, 2 or 5 times a power of ten Hz no less than `min_step`
+fn freq_step(min_step: f64) -> i64 {
+    let mut decade = 1;
+    loop {
+        for m in &[1, 2, 5] {
+            if (decade * m) as f64 >= min_step {
+                return decade * m;
+            }
+        }
+        decade *= 10;
+    }
+}
+
+/// Image row and audio frequency of each frequency label, highest frequency at the top unless
+/// `inverted`
+fn freq_ticks(freq_range: &[i32], height: u32, inverted: bool) -> Vec<(u32, i64)> {
+    let (lo, hi) = (freq_range[0] as i64, freq_range[1] as i64);
+    if hi <= lo || height == 0 {
+        return Vec::new();
+    }
+    let step = freq_step((hi - lo) as f64 / (height / LABEL_SPACING).max(1) as f64);
+    let first = lo.div_euclid(step) * step + if lo.rem_euclid(step) == 0 { 0 } else { step };
+
+    (0..).map(|i| first + i * step)
+        .take_while(|&f| f <= hi)
+        .map(|f| {
+            let from_top = if inverted { f - lo } else { hi - f };
+            (((from_top * height as i64) / (hi - lo)).min(height as i64 - 1) as u32, f)
+        })
+        .collect()
+}
+
+/// Widest of `labels` drawn at scale 1
+fn max_width(labels: &[String]) -> u32 {
+    labels.iter().map(|l| font::text_width(l, 1)).max().unwrap_or(0)
+}
+
+/// `img`, rendered from `grid`, framed by scales and a header describing it
+pub (crate) fn annotate(img: &RgbImage, grid: &Grid, labels: &Labels) -> RgbImage {
+    let (w, h) = img.dimensions();
+    let ticks = freq_ticks(&labels.freq_range, h, labels.radio.inverted());
+
+    // right-aligned columns of the frequency scale, outermost first
+    let mut columns: Vec<(String, Vec<String>)> = Vec::new();
+    if labels.radio.dial_freq > 0 {
+        columns.push((String::from("kHz"), ticks.iter()
+            .filter_map(|(_, f)| labels.radio.rf(*f))
+            .map(|rf| format!("{:.3}", rf as f64 / 1000.))
+            .collect()));
+    }
+    columns.push((String::from("Hz"), ticks.iter().map(|(_, f)| format!("{}", f)).collect()));
+    let widths: Vec<u32> = columns.iter()
+        .map(|(unit, l)| max_width(l).max(font::text_width(unit, 1)))
+        .collect();
+
+    let left = PAD + widths.iter().map(|cw| cw + PAD).sum::<u32>() + TICK_LENGTH;
+    let top = font::HEIGHT * HEADER_SCALE + 2 * PAD;
+    let bottom = TICK_LENGTH + font::HEIGHT + 2 * PAD;
+    let mut out = RgbImage::from_pixel(left + w + PAD, top + h + bottom, MARGIN_COLOR);
+    imageops::replace(&mut out, img, left as i64, top as i64);
+
+    // header: station, band and frame start
+    let stamp = format!("{} UTC", grid.start.format("%Y-%m-%d %H:%M"));
+    let header: Vec<&str> = [
+        labels.station.callsign.as_str(),
+        labels.station.locator.as_str(),
+        labels.radio.band.as_str(),
+        stamp.as_str(),
+    ].iter().copied().filter(|s| !s.is_empty()).collect();
+    font::draw_text(&mut out, left as i64, PAD as i64, &header.join("  "), HEADER_SCALE, TEXT_COLOR);
+
+    // frequency scale, labels centered on their ticks; units below
+    let caption_y = (top + h + TICK_LENGTH + PAD) as i64;
+    let mut x_end = PAD;
+    for ((unit, column), cw) in columns.iter().zip(&widths) {
+        x_end += cw;
+        for ((y, _), label) in ticks.iter().zip(column) {
+            let ly = (top + y) as i64 - font::HEIGHT as i64 / 2;
+            font::draw_text(&mut out, (x_end - font::text_width(label, 1)) as i64, ly, label, 1, TEXT_COLOR);
+        }
+        font::draw_text(&mut out, (x_end - font::text_width(unit, 1)) as i64, caption_y, unit, 1, TEXT_COLOR);
+        x_end += PAD;
+    }
+    for (y, _) in &ticks {
+        for x in left - TICK_LENGTH..left {
+            out.put_pixel(x, top + y, TEXT_COLOR);
+        }
+    }
+
+    // UTC time labels under the ticks drawn into the waterfall, skipping any that would overlap
+    let seconds = spectrum::tick_interval(grid.duration) < 60;
+    let mut free_x = left;
+    for (x, t) in render::time_ticks(grid.start, grid.duration, w) {
+        for y in top + h..top + h + TICK_LENGTH {
+            out.put_pixel(left + x, y, TEXT_COLOR);
+        }
+        let label = t.format(if seconds { "%H:%M:%S" } else { "%H:%M" }).to_string();
+        let lw = font::text_width(&label, 1);
+        let lx = (left + x).saturating_sub(lw / 2).max(left).min(left + w - lw.min(w));
+        if lx >= free_x {
+            font::draw_text(&mut out, lx as i64, caption_y, &label, 1, TEXT_COLOR);
+            free_x = lx + lw + 2 * PAD;
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freq_steps_round_up_to_1_2_5() {
+        for &(min_step, step) in &[(0., 1), (1., 1), (1.1, 2), (3., 5), (7., 10), (135., 200), (333.3, 500), (5000., 5000)] {
+            assert_eq!(freq_step(min_step), step, "{}", min_step);
+        }
+    }
+
+    #[test]
+    fn freq_ticks_follow_the_sideband() {
+        // 200 Hz steps for 20 labels at most, 200 to 2800 Hz
+        let upright = freq_ticks(&[100, 2800], 600, false);
+        let inverted = freq_ticks(&[100, 2800], 600, true);
+        let freqs: Vec<i64> = (1..=14).map(|i| i * 200).collect();
+        assert_eq!(upright.iter().map(|t| t.1).collect::<Vec<_>>(), freqs);
+        assert_eq!(inverted.iter().map(|t| t.1).collect::<Vec<_>>(), freqs);
+
+        // USB rises to the top, LSB runs down from it, mirrored about the middle
+        assert_eq!((upright[0].0, upright[13].0), (577, 0));
+        assert_eq!((inverted[0].0, inverted[13].0), (22, 599));
+        assert!(upright.windows(2).all(|t| t[0].0 > t[1].0));
+        assert!(inverted.windows(2).all(|t| t[0].0 < t[1].0));
+        for (u, i) in upright.iter().zip(&inverted) {
+            // rows are rounded down, and the top edge is clamped to the last row
+            assert!((598..=600).contains(&(u.0 + i.0)), "{} Hz: rows {} and {}", u.1, u.0, i.0);
+        }
+
+        assert!(freq_ticks(&[1000, 1000], 600, false).is_empty());
+        assert!(freq_ticks(&[100, 2800], 0, true).is_empty());
+    }
+
+    #[test]
+    fn freq_ticks_cross_zero_with_iq() {
+        // labels land on whole steps either side of 0 Hz, from a range that isn't on one
+        let ticks = freq_ticks(&[-1234, 766], 200, false);
+        assert_eq!(ticks, vec![(176, -1000), (126, -500), (76, 0), (26, 500)]);
+        let ticks = freq_ticks(&[-1234, 766], 200, true);
+        assert_eq!(ticks, vec![(23, -1000), (73, -500), (123, 0), (173, 500)]);
+
+        // a symmetric range puts 0 Hz in the middle, edges included
+        let ticks = freq_ticks(&[-1500, 1500], 300, false);
+        assert_eq!(ticks.iter().map(|t| t.1).collect::<Vec<_>>(), vec![-1500, -1000, -500, 0, 500, 1000, 1500]);
+        assert_eq!((ticks[0].0, ticks[3].0, ticks[6].0), (299, 150, 0));
+    }
+}
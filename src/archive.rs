@@ -0,0 +1,467 @@
+/// Spectral data archive, one binary file per UTC hour
+///
+/// Files start with a header describing the FFT, followed by timestamped columns holding the
+/// bins of the archived frequency range, all little endian:
+///
+/// | field         | type    |                                                    |
+/// |---------------|---------|----------------------------------------------------|
+/// | magic         | [u8; 4] | `QRSA`                                             |
+/// | version       | u8      | 1                                                  |
+/// | format        | u8      | 0: f32 magnitude, 1: u16 dB, 2: u8 dB              |
+/// | iq            | u8      | 1 for I/Q columns (-Nyquist to Nyquist)            |
+/// | window        | u8      | index in `FftWindowType::ALL`                      |
+/// | sample_rate   | u32     |                                                    |
+/// | fft_size      | u32     |                                                    |
+/// | shift_size    | u32     | samples between columns                            |
+/// | bins          | u32     | bins in a full column                              |
+/// | bin_first     | u32     | first archived bin                                 |
+/// | bin_count     | u32     | archived bins per column                           |
+/// | db_min        | f32     | dB of quantized value 0                            |
+/// | db_step       | f32     | dB per quantized step                              |
+/// | start         | i64     | first column, UTC microseconds since the epoch     |
+///
+/// Each column is its UTC time (i64 microseconds) followed by `bin_count` values.
+
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use super::settings::{ArchiveFormat, FftWindowType};
+use super::spectrum::{self, Column, FreqAxis};
+
+
+pub (crate) const MAGIC: [u8; 4] = *b"QRSA";
+pub (crate) const VERSION: u8 = 1;
+
+/// Archive files cover one UTC hour
+const PERIOD: u32 = 3600;
+
+/// dB kept above the strongest level the FFT can produce when quantizing
+const DB_HEADROOM: f32 = 10.;
+
+#[derive(Debug)]
+pub (crate) enum ArchiveError {
+    DirError(io::Error),   // archive directory creation error
+    IoError(io::Error),    // file read/write error
+    FormatError(String),   // not an archive, or unsupported version
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(e: io::Error) -> Self {
+        ArchiveError::IoError(e)
+    }
+}
+
+/// Microseconds since the epoch, as stored in archives
+fn micros(time: DateTime<Utc>) -> i64 {
+    time.timestamp() * 1_000_000 + time.timestamp_subsec_micros() as i64
+}
+
+fn from_micros(us: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(us.div_euclid(1_000_000), (us.rem_euclid(1_000_000) * 1000) as u32).unwrap()
+}
+
+/// File name for an archive: `spec_<YYYYmmdd>_<HHMMSS>.qsa`, stamped with its first column, and
+/// numbered `_<n>` after the time if `n` isn't 0
+pub (crate) fn file_name(time: DateTime<Utc>, n: u32) -> String {
+    match n {
+        0 => format!("spec_{}.qsa", time.format("%Y%m%d_%H%M%S")),
+        n => format!("spec_{}_{}.qsa", time.format("%Y%m%d_%H%M%S"), n),
+    }
+}
+
+/// Create the archive file for a first column at `time`, numbering it when files started the
+/// same second (layout changes, restarts) already exist, so none is overwritten
+fn create_file(dir: &Path, time: DateTime<Utc>) -> io::Result<(PathBuf, File)> {
+    let mut n = 0;
+    loop {
+        let path = dir.join(file_name(time, n));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl ArchiveFormat {
+    pub (crate) fn code(&self) -> u8 {
+        match self {
+            ArchiveFormat::F32 => 0,
+            ArchiveFormat::U16 => 1,
+            ArchiveFormat::U8  => 2,
+        }
+    }
+
+    pub (crate) fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(ArchiveFormat::F32),
+            1 => Some(ArchiveFormat::U16),
+            2 => Some(ArchiveFormat::U8),
+            _ => None,
+        }
+    }
+
+    /// dB of quantized value 0 and dB per step; 0.01 dB steps for u16, 0.75 dB for u8
+    ///
+    /// Levels depend on the FFT: with windows of unit mean, a bin reaches at most
+    /// `window_size / sqrt(fft_size)`, times sqrt(2) for full-scale I and Q together. The scale tops out `DB_HEADROOM` above that, as
+    /// background subtraction can lift a bin a little further, and reaches down as far as the
+    /// format allows.
+    pub (crate) fn db_scale(&self, plan: &spectrum::FftPlan) -> (f32, f32) {
+        let full_scale = spectrum::db(plan.window_size as f32 * 2_f32.sqrt() / (plan.fft_size as f32).sqrt());
+        let db_max = full_scale.ceil() + DB_HEADROOM;
+        match self {
+            ArchiveFormat::F32 => (0., 0.),
+            ArchiveFormat::U16 => (db_max - u16::MAX as f32 * 0.01, 0.01),
+            ArchiveFormat::U8  => (db_max - u8::MAX as f32 * 0.75, 0.75),
+        }
+    }
+}
+
+/// Everything in a header but the start time; a change starts a new file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) struct Layout {
+    pub format:      ArchiveFormat,
+    pub iq:          bool,
+    pub window:      FftWindowType,
+    pub sample_rate: u32,
+    pub fft_size:    u32,
+    pub shift_size:  u32,
+    pub bins:        u32,
+    pub bin_first:   u32,
+    pub bin_count:   u32,
+    pub db_min:      f32,
+    pub db_step:     f32,
+}
+
+impl Layout {
+    /// Layout archiving the bins of `column` covering `freq_range`
+    pub (crate) fn new(
+        format: ArchiveFormat,
+        window: FftWindowType,
+        plan: &spectrum::FftPlan,
+        column: &Column,
+        freq_range: &[i32],
+    ) -> Self {
+        let axis = FreqAxis::of(column);
+        let (lowest, last) = (column.first_bin as f32, (column.first_bin + column.bins.len()).saturating_sub(1) as f32);
+        let first = axis.bin(freq_range[0] as f32).floor().max(lowest).min(last) as u32;
+        let end = axis.bin(freq_range[1] as f32).ceil().max(lowest).min(last) as u32;
+        let (db_min, db_step) = format.db_scale(plan);
+        Layout {
+            format,
+            iq:          column.iq,
+            window,
+            sample_rate: column.sample_rate,
+            fft_size:    plan.fft_size,
+            shift_size:  plan.shift_size,
+            bins:        column.total_bins as u32,
+            bin_first:   first,
+            bin_count:   end.max(first) - first + 1,
+            db_min,
+            db_step,
+        }
+    }
+
+    fn write_header<W: Write>(&self, w: &mut W, start: DateTime<Utc>) -> io::Result<()> {
+        let window = FftWindowType::ALL.iter().position(|t| *t == self.window).unwrap() as u8;
+        w.write_all(&MAGIC)?;
+        w.write_all(&[VERSION, self.format.code(), self.iq as u8, window])?;
+        for v in &[self.sample_rate, self.fft_size, self.shift_size, self.bins, self.bin_first, self.bin_count] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        w.write_all(&self.db_min.to_le_bytes())?;
+        w.write_all(&self.db_step.to_le_bytes())?;
+        w.write_all(&micros(start).to_le_bytes())
+    }
+
+    fn write_column<W: Write>(&self, w: &mut W, column: &Column) -> io::Result<()> {
+        w.write_all(&micros(column.time).to_le_bytes())?;
+        let (first, count) = (self.bin_first as usize - column.first_bin, self.bin_count as usize);
+        let quantize = |b: f32| ((spectrum::db(b) - self.db_min) / self.db_step).round().max(0.);
+
+        for &b in &column.bins[first..first + count] {
+            match self.format {
+                ArchiveFormat::F32 => w.write_all(&b.to_le_bytes())?,
+                ArchiveFormat::U16 => w.write_all(&(quantize(b).min(u16::MAX as f32) as u16).to_le_bytes())?,
+                ArchiveFormat::U8  => w.write_all(&[quantize(b).min(u8::MAX as f32) as u8])?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Archive file being written, its layout and the UTC hour it covers
+struct Open {
+    layout: Layout,
+    hour:   DateTime<Utc>,
+    path:   PathBuf,
+    writer: BufWriter<File>,
+}
+
+/// Writes FFT columns into one file per UTC hour
+pub (crate) struct Archiver {
+    dir:  PathBuf,
+    file: Option<Open>,
+}
+
+impl Archiver {
+    pub (crate) fn new(dir: &Path) -> Self {
+        Archiver {
+            dir:  dir.to_path_buf(),
+            file: None,
+        }
+    }
+
+    /// Append `column`, starting a new file at hour boundaries and layout changes
+    ///
+    /// Returns the file closed to make way for a new one.
+    pub (crate) fn write(&mut self, layout: Layout, column: &Column) -> Result<Option<PathBuf>, ArchiveError> {
+        let hour = spectrum::frame_start(column.time, PERIOD);
+        let mut closed = None;
+        if self.file.as_ref().map_or(true, |f| f.layout != layout || f.hour != hour) {
+            closed = self.finish()?;
+
+            fs::create_dir_all(&self.dir).map_err(ArchiveError::DirError)?;
+            let (path, file) = create_file(&self.dir, column.time)?;
+            let mut writer = BufWriter::new(file);
+            layout.write_header(&mut writer, column.time)?;
+            self.file = Some(Open { layout, hour, path, writer });
+        }
+
+        let f = self.file.as_mut().unwrap();
+        f.layout.write_column(&mut f.writer, column)?;
+        Ok(closed)
+    }
+
+    /// Close the current file, if any
+    pub (crate) fn finish(&mut self) -> Result<Option<PathBuf>, ArchiveError> {
+        match self.file.take() {
+            Some(mut f) => {
+                f.writer.flush()?;
+                Ok(Some(f.path))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(f32::from_le_bytes(b))
+}
+
+fn read_i64<R: Read>(r: &mut R) -> io::Result<i64> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(i64::from_le_bytes(b))
+}
+
+/// Decode the archive at `path`, handing each column to `f`
+///
+/// Columns hold the archived bins only, cropped like columns kept in history. Returns the layout.
+pub (crate) fn read_file<F: FnMut(Column)>(path: &Path, mut f: F) -> Result<Layout, ArchiveError> {
+    let mut r = BufReader::new(File::open(path)?);
+    let format_error = |what: &str| ArchiveError::FormatError(format!("{}: {:?}", what, path));
+
+    let mut head = [0; 8];
+    r.read_exact(&mut head)?;
+    if head[..4] != MAGIC {
+        return Err(format_error("Not a spectral archive"));
+    }
+    if head[4] != VERSION {
+        return Err(format_error("Unsupported archive version"));
+    }
+    let format = ArchiveFormat::from_code(head[5]).ok_or_else(|| format_error("Unknown sample format"))?;
+    let window = *FftWindowType::ALL.get(head[7] as usize).ok_or_else(|| format_error("Unknown window"))?;
+    let mut fields = [0_u32; 6];
+    for v in fields.iter_mut() {
+        *v = read_u32(&mut r)?;
+    }
+    let layout = Layout {
+        format,
+        iq:          head[6] != 0,
+        window,
+        sample_rate: fields[0],
+        fft_size:    fields[1],
+        shift_size:  fields[2],
+        bins:        fields[3],
+        bin_first:   fields[4],
+        bin_count:   fields[5],
+        db_min:      read_f32(&mut r)?,
+        db_step:     read_f32(&mut r)?,
+    };
+    if layout.sample_rate == 0 || layout.bin_first as u64 + layout.bin_count as u64 > layout.bins as u64 {
+        return Err(format_error("Corrupt header"));
+    }
+    let _start = read_i64(&mut r)?;
+
+    let interval = Duration::microseconds(
+        (layout.shift_size as u64 * 1_000_000 / layout.sample_rate as u64) as i64);
+    let first = layout.bin_first as usize;
+    let mut raw = vec![0_u8; layout.bin_count as usize * match format {
+        ArchiveFormat::F32 => 4,
+        ArchiveFormat::U16 => 2,
+        ArchiveFormat::U8  => 1,
+    }];
+
+    loop {
+        // a file cut short (still being written) ends after its last whole column
+        let time = match read_i64(&mut r) {
+            Ok(t) => from_micros(t),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        match r.read_exact(&mut raw) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            other => other?,
+        }
+
+        let level = |q: f32| 10_f32.powf((layout.db_min + q * layout.db_step) / 20.);
+        let bins: Vec<f32> = match format {
+            ArchiveFormat::F32 => raw.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect(),
+            ArchiveFormat::U16 => raw.chunks_exact(2).map(|b| level(u16::from_le_bytes([b[0], b[1]]) as f32)).collect(),
+            ArchiveFormat::U8  => raw.iter().map(|&b| level(b as f32)).collect(),
+        };
+
+        f(Column {
+            time,
+            interval,
+            sample_rate: layout.sample_rate,
+            iq:          layout.iq,
+            total_bins:  layout.bins as usize,
+            first_bin:   first,
+            bins,
+        });
+    }
+    Ok(layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use super::super::analyzer::Analyzer;
+    use super::super::settings::Settings;
+    use super::super::spectrum::FftPlan;
+
+    fn column(time: DateTime<Utc>, bins: Vec<f32>) -> Column {
+        Column {
+            time,
+            interval:    Duration::seconds(1),
+            sample_rate: 8000,
+            iq:          false,
+            total_bins:  bins.len(),
+            first_bin:   0,
+            bins,
+        }
+    }
+
+    fn layout(format: ArchiveFormat, column: &Column) -> Layout {
+        let plan = FftPlan::new(8000, 4., 0, 1);
+        Layout::new(format, FftWindowType::Hann, &plan, column, &[0, 4000])
+    }
+
+    #[test]
+    fn round_trip_within_quantization() {
+        let t = Utc.timestamp_opt(1_609_502_400, 123_456_000).unwrap();
+        // levels from -130 dB to +27.5 dB, within the range of every format for this plan
+        let bins: Vec<f32> = (0..64).map(|i| 10_f32.powf((i as f32 * 2.5 - 130.) / 20.)).collect();
+        let columns = vec![column(t, bins.clone()), column(t + Duration::milliseconds(750), bins.clone())];
+
+        for &(format, tolerance) in &[(ArchiveFormat::F32, 0.), (ArchiveFormat::U16, 0.006), (ArchiveFormat::U8, 0.38)] {
+            let dir = tempfile::tempdir().unwrap();
+            let mut archiver = Archiver::new(dir.path());
+            // archive only 1000-3000 Hz of the 0-4000 Hz column
+            let plan = FftPlan::new(8000, 4., 0, 1);
+            let layout = Layout::new(format, FftWindowType::Hann, &plan, &columns[0], &[1000, 3000]);
+            for c in &columns {
+                archiver.write(layout, c).unwrap();
+            }
+            let path = archiver.finish().unwrap().unwrap();
+
+            let mut read = Vec::new();
+            assert_eq!(read_file(&path, |c| read.push(c)).unwrap(), layout);
+            assert_eq!(read.len(), columns.len());
+            for (r, c) in read.iter().zip(&columns) {
+                assert_eq!(r.time, c.time);
+                assert_eq!((r.sample_rate, r.iq, r.total_bins), (c.sample_rate, c.iq, c.bins.len()));
+                assert_eq!((r.first_bin, r.bins.len()), (layout.bin_first as usize, layout.bin_count as usize));
+                for (i, (&a, &b)) in r.bins.iter().zip(&c.bins[r.first_bin..]).enumerate() {
+                    assert!((spectrum::db(a) - spectrum::db(b)).abs() <= tolerance,
+                        "{:?} bin {}: {} dB read, {} dB written", format, r.first_bin + i, spectrum::db(a), spectrum::db(b));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn files_started_the_same_second_are_numbered() {
+        let dir = tempfile::tempdir().unwrap();
+        let t = Utc.timestamp_opt(1_609_502_400, 0).unwrap();
+        let first = column(t, vec![0.5; 16]);
+        let second = column(t + Duration::milliseconds(300), vec![0.25; 16]);
+
+        // a layout change within the second, then a restart within the same second
+        let mut archiver = Archiver::new(dir.path());
+        archiver.write(layout(ArchiveFormat::F32, &first), &first).unwrap();
+        let a = archiver.write(layout(ArchiveFormat::U16, &second), &second).unwrap().unwrap();
+        let b = archiver.finish().unwrap().unwrap();
+        let mut restarted = Archiver::new(dir.path());
+        restarted.write(layout(ArchiveFormat::U8, &second), &second).unwrap();
+        let c = restarted.finish().unwrap().unwrap();
+
+        assert_eq!(a, dir.path().join("spec_20210101_120000.qsa"));
+        assert_eq!(b, dir.path().join("spec_20210101_120000_1.qsa"));
+        assert_eq!(c, dir.path().join("spec_20210101_120000_2.qsa"));
+        for (path, format, value) in &[(a, ArchiveFormat::F32, 0.5), (b, ArchiveFormat::U16, 0.25), (c, ArchiveFormat::U8, 0.25)] {
+            let mut columns = Vec::new();
+            assert_eq!(read_file(path, |c| columns.push(c)).unwrap().format, *format);
+            assert_eq!(columns.len(), 1);
+            assert!((spectrum::db(columns[0].bins[3]) - spectrum::db(*value)).abs() < 0.5);
+        }
+    }
+
+    #[test]
+    fn full_scale_tone_fits_every_format() {
+        // the default plan analyzes 1.5 s windows at 48 kHz, so a full-scale tone peaks near +40 dB
+        let set = Settings::default();
+        let t = Utc.timestamp_opt(1_609_502_400, 0).unwrap();
+        let mut analyzer = Analyzer::new(&set, t);
+        let tone: Vec<f32> = (0..3 * set.audio.rate).map(|i| (2. * PI * 1000. * i as f32 / set.audio.rate as f32).sin()).collect();
+        let mut columns = Vec::new();
+        analyzer.process(&tone, |c| columns.push(c));
+        let peak = |c: &Column| c.bins.iter().cloned().fold(0_f32, f32::max);
+        assert!(spectrum::db(peak(&columns[0])) > 39., "tone at {} dB", spectrum::db(peak(&columns[0])));
+
+        for &(format, tolerance) in &[(ArchiveFormat::F32, 0.), (ArchiveFormat::U16, 0.006), (ArchiveFormat::U8, 0.38)] {
+            let dir = tempfile::tempdir().unwrap();
+            let mut archiver = Archiver::new(dir.path());
+            let layout = Layout::new(format, FftWindowType::Hann, analyzer.plan(), &columns[0], &[900, 1100]);
+            for c in &columns {
+                archiver.write(layout, c).unwrap();
+            }
+            let path = archiver.finish().unwrap().unwrap();
+
+            let mut read = Vec::new();
+            read_file(&path, |c| read.push(c)).unwrap();
+            assert_eq!(read.len(), columns.len());
+            for (r, c) in read.iter().zip(&columns) {
+                assert!((spectrum::db(peak(r)) - spectrum::db(peak(c))).abs() <= tolerance,
+                    "{:?}: {} dB read, {} dB written", format, spectrum::db(peak(r)), spectrum::db(peak(c)));
+            }
+        }
+    }
+}
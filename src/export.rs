@@ -0,0 +1,346 @@
+/// Image export of finished frames
+
+
+use std::io::{self, BufWriter, Write};
+use std::fs::{self, File, OpenOptions};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use image::{self, ImageOutputFormat, RgbImage};
+
+use super::annotate::{self, Labels};
+use super::render::{self, Grid};
+use super::settings;
+use super::spectrum;
+
+
+#[derive(Debug)]
+pub (crate) enum ExportError {
+    DirError(io::Error),            // export directory or file creation error
+    ImageError(image::ImageError),  // image encode/write error
+}
+
+/// File name for an export: `<name>_<YYYYmmdd>_<HHMM>.png`, stamped with the frame start, and
+/// numbered `_<n>` after the time if `n` isn't 0
+pub (crate) fn file_name(name: &str, time: DateTime<Utc>, n: u32) -> String {
+    match n {
+        0 => format!("{}_{}.png", name, time.format("%Y%m%d_%H%M")),
+        n => format!("{}_{}_{}.png", name, time.format("%Y%m%d_%H%M"), n),
+    }
+}
+
+/// Create the image file for `name` and `time`, numbering it when one exists already: a frame
+/// cut short by a layout change is followed by another starting at the same time
+fn create_file(dir: &Path, name: &str, time: DateTime<Utc>) -> io::Result<(PathBuf, File)> {
+    let mut n = 0;
+    loop {
+        let path = dir.join(file_name(name, time, n));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Write `img` into `dir` as PNG, creating the directory if needed
+pub (crate) fn save(img: &RgbImage, dir: &Path, name: &str, time: DateTime<Utc>) -> Result<PathBuf, ExportError> {
+    fs::create_dir_all(dir).map_err(ExportError::DirError)?;
+    let (path, file) = create_file(dir, name, time).map_err(ExportError::DirError)?;
+    let mut w = BufWriter::new(file);
+    img.write_to(&mut w, ImageOutputFormat::Png).map_err(ExportError::ImageError)?;
+    w.flush().map_err(|e| ExportError::ImageError(image::ImageError::IoError(e)))?;
+    Ok(path)
+}
+
+/// Image of `grid` as exported: annotated with `labels` unless turned off in `export`
+pub (crate) fn export_image(grid: &Grid, image: &settings::Image, export: &settings::Export, labels: &Labels) -> RgbImage {
+    let img = render::image(grid, image);
+    match export.annotate {
+        true  => annotate::annotate(&img, grid, labels),
+        false => img,
+    }
+}
+
+/// Keeps the most recent finished frames for averaged and peak-hold images
+pub (crate) struct FrameStack {
+    frames: VecDeque<Grid>,
+}
+
+impl FrameStack {
+    pub (crate) fn new() -> Self {
+        FrameStack {
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Add a finished frame, keeping at most `depth` frames
+    ///
+    /// Frames of a different size can't be combined, so a resize starts the stack over.
+    pub (crate) fn push(&mut self, grid: Grid, depth: usize) {
+        if self.frames.back().map_or(false, |g| !g.same_shape(&grid)) {
+            self.frames.clear();
+        }
+        self.frames.push_back(grid);
+        while self.frames.len() > depth.max(1) {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Most recent frame
+    pub (crate) fn latest(&self) -> Option<&Grid> {
+        self.frames.back()
+    }
+
+    /// Pixel-wise mean of all kept frames, stamped with the latest frame's time
+    pub (crate) fn average(&self) -> Option<Grid> {
+        let mut avg = self.latest()?.clone();
+        let n = self.frames.len() as f32;
+        for (i, v) in avg.data.iter_mut().enumerate() {
+            *v = self.frames.iter().map(|g| g.data[i]).sum::<f32>() / n;
+        }
+        Some(avg)
+    }
+
+    /// Pixel-wise maximum of all kept frames, stamped with the latest frame's time
+    pub (crate) fn peak(&self) -> Option<Grid> {
+        let mut pk = self.latest()?.clone();
+        for (i, v) in pk.data.iter_mut().enumerate() {
+            *v = self.frames.iter().map(|g| g.data[i]).fold(0_f32, f32::max);
+        }
+        Some(pk)
+    }
+
+    /// Render and save the images enabled in `export`, returning the paths written
+    pub (crate) fn export(
+        &self,
+        image: &settings::Image,
+        export: &settings::Export,
+        names: &settings::Names,
+        labels: &Labels,
+    ) -> Vec<Result<PathBuf, ExportError>> {
+        let mut written = Vec::new();
+        let grids = [
+            (export.single,  &names.single,  self.latest().cloned()),
+            (export.average, &names.average, if export.average { self.average() } else { None }),
+            (export.peak,    &names.peak,    if export.peak    { self.peak()    } else { None }),
+        ];
+        for (enabled, name, grid) in grids.iter() {
+            if let (true, Some(grid)) = (*enabled, grid) {
+                written.push(save(&export_image(grid, image, export, labels), &export.path, name, grid.start));
+            }
+        }
+        written
+    }
+}
+
+/// Long-exposure image covering a fixed UTC period (an hour, a day)
+///
+/// Finished frames are squeezed into the period's width, keeping the strongest value of every
+/// pixel so short traces survive the compression.
+pub (crate) struct Overview {
+    period: u32,
+    grid:   Option<Grid>,
+}
+
+impl Overview {
+    pub (crate) fn new(period: u32) -> Self {
+        Overview {
+            period,
+            grid: None,
+        }
+    }
+
+    /// Pool a finished frame into the overview
+    ///
+    /// Returns the overviews completed by crossing a period boundary, ready to be saved.
+    pub (crate) fn add(&mut self, frame: &Grid) -> Vec<Grid> {
+        let mut done = Vec::new();
+        let (fw, fh) = (frame.width as usize, frame.height as usize);
+
+        for fx in 0..fw {
+            let t = frame.start + Duration::milliseconds(
+                fx as i64 * frame.duration as i64 * 1000 / fw as i64);
+            let start = spectrum::frame_start(t, self.period);
+
+            // roll over at period boundaries; a resize also starts over
+            if self.grid.as_ref().map_or(true, |g| g.start != start || g.height != frame.height) {
+                done.extend(self.grid.take());
+                self.grid = Some(Grid::new(start, self.period, frame.width, frame.height));
+            }
+
+            let g = self.grid.as_mut().unwrap();
+            let gw = g.width as usize;
+            let ox = ((t - start).num_milliseconds() * gw as i64 / (self.period as i64 * 1000)) as usize;
+            for y in 0..fh {
+                let v = frame.data[y * fw + fx];
+                let o = &mut g.data[y * gw + ox];
+                *o = o.max(v);
+            }
+        }
+        done
+    }
+
+    /// Overview still in progress, removed so it can be saved early (at shutdown)
+    pub (crate) fn take(&mut self) -> Option<Grid> {
+        self.grid.take()
+    }
+}
+
+/// Everything built from finished frames: stacked frames plus hourly and daily overviews
+pub (crate) struct Exporter {
+    frames: FrameStack,
+    hour:   Overview,
+    day:    Overview,
+}
+
+impl Exporter {
+    pub (crate) fn new() -> Self {
+        Exporter {
+            frames: FrameStack::new(),
+            hour:   Overview::new(3600),
+            day:    Overview::new(86400),
+        }
+    }
+
+    /// Save overviews that are enabled in `export`
+    fn save_overviews(
+        hours: Vec<Grid>,
+        days: Vec<Grid>,
+        image: &settings::Image,
+        export: &settings::Export,
+        names: &settings::Names,
+        labels: &Labels,
+    ) -> Vec<Result<PathBuf, ExportError>> {
+        let hours = hours.into_iter().filter(|_| export.hour).map(|g| (g, &names.hour));
+        let days  = days .into_iter().filter(|_| export.day ).map(|g| (g, &names.day));
+        hours.chain(days)
+            .filter(|_| export.export_enable)
+            .map(|(g, name)| save(&export_image(&g, image, export, labels), &export.path, name, g.start))
+            .collect()
+    }
+
+    /// Combine a finished frame into stack and overviews, saving images enabled in `export`
+    pub (crate) fn finish_frame(
+        &mut self,
+        grid: Grid,
+        image: &settings::Image,
+        export: &settings::Export,
+        names: &settings::Names,
+        labels: &Labels,
+    ) -> Vec<Result<PathBuf, ExportError>> {
+        let hours = self.hour.add(&grid);
+        let days = self.day.add(&grid);
+        let mut written = Exporter::save_overviews(hours, days, image, export, names, labels);
+
+        self.frames.push(grid, export.average_frames as usize);
+        if export.export_enable {
+            written.extend(self.frames.export(image, export, names, labels));
+        }
+        written
+    }
+
+    /// Save overviews still in progress
+    pub (crate) fn flush(
+        &mut self,
+        image: &settings::Image,
+        export: &settings::Export,
+        names: &settings::Names,
+        labels: &Labels,
+    ) -> Vec<Result<PathBuf, ExportError>> {
+        let hours = self.hour.take().into_iter().collect();
+        let days = self.day.take().into_iter().collect();
+        Exporter::save_overviews(hours, days, image, export, names, labels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn images_of_a_split_frame_are_numbered() {
+        let dir = tempfile::tempdir().unwrap();
+        let t = Utc.timestamp_opt(1_609_502_400, 0).unwrap();
+        let first = RgbImage::from_pixel(4, 3, image::Rgb([255, 0, 0]));
+        let second = RgbImage::from_pixel(6, 3, image::Rgb([0, 0, 255]));
+
+        // a frame cut short by a resize, then the rest of it, both starting at 12:00
+        let a = save(&first, dir.path(), "single", t).unwrap();
+        let b = save(&second, dir.path(), "single", t).unwrap();
+
+        assert_eq!(a, dir.path().join("single_20210101_1200.png"));
+        assert_eq!(b, dir.path().join("single_20210101_1200_1.png"));
+        assert_eq!(image::open(&a).unwrap().to_rgb8(), first);
+        assert_eq!(image::open(&b).unwrap().to_rgb8(), second);
+    }
+
+    /// Frame of `width` columns of 6 minutes each, row 0 counting up from `first`, row 1 constant
+    fn frame(start: DateTime<Utc>, width: u32, first: f32) -> Grid {
+        let mut grid = Grid::new(start, width * 360, width, 2);
+        for x in 0..width as usize {
+            grid.data[x] = first + x as f32;
+            grid.data[width as usize + x] = 0.5;
+        }
+        grid
+    }
+
+    #[test]
+    fn overview_rolls_over_at_the_hour() {
+        let noon = Utc.timestamp_opt(1_609_502_400, 0).unwrap();
+        // 11:30 to 12:30, one column every 6 minutes like the hourly overview of the same width
+        let first = frame(noon - Duration::minutes(30), 10, 1.);
+        let mut hour = Overview::new(3600);
+        let mut day = Overview::new(86400);
+
+        let done = hour.add(&first);
+        assert!(day.add(&first).is_empty());
+        assert_eq!(done.len(), 1);
+        let before = &done[0];
+        assert_eq!((before.start, before.duration, before.width, before.height), (noon - Duration::hours(1), 3600, 10, 2));
+        // columns up to 11:54 fill the second half of the 11:00 hour
+        assert_eq!(&before.data[..10], &[0., 0., 0., 0., 0., 1., 2., 3., 4., 5.]);
+        assert_eq!(&before.data[10..], &[0., 0., 0., 0., 0., 0.5, 0.5, 0.5, 0.5, 0.5]);
+
+        // a later frame overlapping 12:24 keeps the stronger value there
+        assert!(hour.add(&frame(noon + Duration::minutes(24), 2, 7.)).is_empty());
+        let after = hour.take().unwrap();
+        assert_eq!(after.start, noon);
+        assert_eq!(&after.data[..10], &[6., 7., 8., 9., 10., 8., 0., 0., 0., 0.]);
+        assert!(hour.take().is_none());
+        assert_eq!(day.take().unwrap().start, noon - Duration::hours(12));
+    }
+
+    #[test]
+    fn stack_keeps_the_latest_frames() {
+        let noon = Utc.timestamp_opt(1_609_502_400, 0).unwrap();
+        let mut stack = FrameStack::new();
+        assert!(stack.latest().is_none() && stack.average().is_none() && stack.peak().is_none());
+
+        // five frames of level k, one deeper than kept, the third with a spike
+        for k in 1..=5 {
+            let mut grid = Grid::new(noon + Duration::minutes(10 * k), 600, 4, 1);
+            grid.data = vec![k as f32; 4];
+            if k == 3 {
+                grid.data[1] = 20.;
+            }
+            stack.push(grid, 3);
+        }
+        let (avg, pk) = (stack.average().unwrap(), stack.peak().unwrap());
+        assert_eq!(stack.latest().unwrap().start, noon + Duration::minutes(50));
+        assert_eq!((avg.start, pk.start), (noon + Duration::minutes(50), noon + Duration::minutes(50)));
+        assert_eq!(avg.data, vec![4., (20. + 4. + 5.) / 3., 4., 4.]);
+        assert_eq!(pk.data, vec![5., 20., 5., 5.]);
+
+        // once the spike ages out of the stack it's gone from both
+        stack.push(Grid::new(noon + Duration::hours(1), 600, 4, 1), 3);
+        assert_eq!(stack.average().unwrap().data, vec![3.; 4]);
+        assert_eq!(stack.peak().unwrap().data, vec![5.; 4]);
+
+        // a resized frame starts over
+        stack.push(Grid::new(noon + Duration::minutes(70), 600, 5, 1), 3);
+        assert_eq!(stack.average().unwrap().data, vec![0.; 5]);
+    }
+}
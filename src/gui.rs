@@ -1,11 +1,12 @@
 /// Build and init GTK GUI
 
 
-use std::str::FromStr;
-use std::path::PathBuf;
+use std::rc::Rc;
+use std::cell::RefCell;
 use std::sync::{Arc, Mutex, Condvar};
 
 use super::settings;
+use super::spectrum;
 
 // GTK+
 use glib::clone;
@@ -16,27 +17,47 @@ use gtk::{
     Button,
     CheckButton,
     ComboBox,
+    DrawingArea,
     Entry,
     FileChooserButton,
     ListStore,
+    Label,
     Popover,
     SpinButton,
 };
+use gtk::gdk_pixbuf::{Colorspace, Pixbuf};
 use gtk::prelude::*;
 // use gio::prelude::*;
 
 // Logging
 use slog;
 
+// Image
+use image::RgbImage;
+
 // Audio
 use cpal;
 use cpal::traits::*;
 
+/// Time and frequency covered by one pixel and one FFT column with the current settings
+fn scale_text(set: &settings::Settings) -> String {
+    let rate = set.audio.rate;
+    let plan = spectrum::FftPlan::new(rate, set.fft.resolution, set.fft.overlap, set.frame.dot_length);
+    let span = (set.audio.freq_range[1] - set.audio.freq_range[0]) as f32;
+    format!("{:.3} Hz/px ({:.3} Hz/bin), {:.2} s/px ({:.2} s/column)",
+        span / set.image.dimensions[1] as f32,
+        plan.hz_per_bin(rate),
+        set.frame.duration as f32 / set.image.dimensions[0] as f32,
+        plan.shift_size as f32 / rate as f32)
+}
+
 pub (crate) fn build_gtk(
     set: Arc<Mutex<settings::Settings>>,
     logger: &slog::Logger,
     cvar_ui_stream: Arc<(Mutex<bool>, Condvar)>,
-    quit_condition: Arc<Mutex<bool>>
+    fft_restart: Arc<Mutex<bool>>,
+    quit_condition: Arc<Mutex<bool>>,
+    rx_img: glib::Receiver<RgbImage>,
 ) {
     if gtk::init().is_err() {
         crit!(logger, "GTK+ init failure.");
@@ -59,6 +80,7 @@ pub (crate) fn build_gtk(
     let button_about:    Button            = builder.object("button_about").unwrap();
     let button_help:     Button            = builder.object("button_help").unwrap();
     let _button_options: Button            = builder.object("button_options").unwrap();
+    let waterfall:       DrawingArea       = builder.object("waterfall").unwrap();
 
     // Extract Settings
     let _combo_devices:  ComboBox          = builder.object("combo_devices").unwrap();
@@ -69,17 +91,57 @@ pub (crate) fn build_gtk(
     let list_rate:       ListStore         = builder.object("list_rate").unwrap();
     let entry_rate:      Entry             = builder.object("entry_rate").unwrap();
 
+    let _combo_format:   ComboBox          = builder.object("combo_format").unwrap();
+    let list_format:     ListStore         = builder.object("list_format").unwrap();
+    let entry_format:    Entry             = builder.object("entry_format").unwrap();
+
+    let check_iq:        CheckButton       = builder.object("check_iq").unwrap();
+
+    let _combo_window:   ComboBox          = builder.object("combo_window").unwrap();
+    let list_window:     ListStore         = builder.object("list_window").unwrap();
+    let entry_window:    Entry             = builder.object("entry_window").unwrap();
+
     let spin_freq_min:   SpinButton        = builder.object("spin_freq_min").unwrap();
     let spin_freq_max:   SpinButton        = builder.object("spin_freq_max").unwrap();
 
+    let spin_frame:      SpinButton        = builder.object("spin_frame_duration").unwrap();
+    let spin_dot:        SpinButton        = builder.object("spin_dot_length").unwrap();
+
+    let spin_resolution: SpinButton        = builder.object("spin_resolution").unwrap();
+    let spin_overlap:    SpinButton        = builder.object("spin_overlap").unwrap();
+    let label_scale:     Label             = builder.object("label_scale").unwrap();
+    let check_bg:        CheckButton       = builder.object("check_background").unwrap();
+    let spin_bg:         SpinButton        = builder.object("spin_bg_percentile").unwrap();
+
+    let check_blanker:   CheckButton       = builder.object("check_blanker").unwrap();
+    let spin_blanker:    SpinButton        = builder.object("spin_blanker_threshold").unwrap();
+    let check_notch:     CheckButton       = builder.object("check_notch").unwrap();
+    let spin_notch:      SpinButton        = builder.object("spin_notch_threshold").unwrap();
+
     let spin_brightness: SpinButton        = builder.object("spin_brightness").unwrap();
     let spin_contrast:   SpinButton        = builder.object("spin_contrast").unwrap();
+    let check_auto:      CheckButton       = builder.object("check_auto_level").unwrap();
+
+    let _combo_palette:  ComboBox          = builder.object("combo_palette").unwrap();
+    let list_palette:    ListStore         = builder.object("list_palette").unwrap();
+    let entry_palette:   Entry             = builder.object("entry_palette").unwrap();
+    let palette_chooser: FileChooserButton = builder.object("palette_filechooser").unwrap();
 
     let check_win_xy:    CheckButton       = builder.object("check_use_window_dim").unwrap();
     let spin_width:      SpinButton        = builder.object("image_width").unwrap();
     let spin_height:     SpinButton        = builder.object("image_height").unwrap();
 
     let check_export:    CheckButton       = builder.object("export_images").unwrap();
+    let check_annotate:  CheckButton       = builder.object("check_annotate").unwrap();
+    let entry_callsign:  Entry             = builder.object("entry_callsign").unwrap();
+    let entry_locator:   Entry             = builder.object("entry_locator").unwrap();
+    let spin_dial:       SpinButton        = builder.object("spin_dial").unwrap();
+    let _combo_band:     ComboBox          = builder.object("combo_band").unwrap();
+    let list_band:       ListStore         = builder.object("list_band").unwrap();
+    let entry_band:      Entry             = builder.object("entry_band").unwrap();
+    let _combo_sideband: ComboBox          = builder.object("combo_sideband").unwrap();
+    let list_sideband:   ListStore         = builder.object("list_sideband").unwrap();
+    let entry_sideband:  Entry             = builder.object("entry_sideband").unwrap();
 
     let check_single:    CheckButton       = builder.object("check_single").unwrap();
     let check_average:   CheckButton       = builder.object("check_average").unwrap();
@@ -98,25 +160,67 @@ pub (crate) fn build_gtk(
     for e in &["16000", "32000", "44100", "48000", "96000", "192000"] {
         list_rate.insert_with_values(None, &[(0, e)]);
     }
+    for e in &["i16", "u16", "f32"] {
+        list_format.insert_with_values(None, &[(0, e)]);
+    }
+    for w in &settings::FftWindowType::ALL {
+        list_window.insert_with_values(None, &[(0, &w.name())]);
+    }
+    for p in &settings::Palette::ALL {
+        list_palette.insert_with_values(None, &[(0, &p.name())]);
+    }
+    for b in &settings::BANDS {
+        list_band.insert_with_values(None, &[(0, &b.name)]);
+    }
+    for e in &["USB", "LSB"] {
+        list_sideband.insert_with_values(None, &[(0, e)]);
+    }
 
     // Load settings into UI
     {
         let set = set.lock().unwrap();
         entry_dev      .set_text(&set.audio.device);
         entry_rate     .set_text(&format!("{}", set.audio.rate));
-        // entry_format   .set_text(match &set.audio.format {
-        //     settings::AudioFormat::i16 => "i16",
-        //     settings::AudioFormat::u16 => "u16",
-        //     settings::AudioFormat::f32 => "f32",
-        // });
+        entry_format   .set_text(match &set.audio.format {
+            settings::AudioFormat::i16 => "i16",
+            settings::AudioFormat::u16 => "u16",
+            settings::AudioFormat::f32 => "f32",
+        });
+        check_iq       .set_active(set.audio.iq);
+        entry_window   .set_text(set.fft.window.name());
+        spin_freq_min  .set_range(set.audio.min_freq() as f64, 96000.);
+        spin_freq_max  .set_range(set.audio.min_freq() as f64, 96000.);
         spin_freq_min  .set_value(set.audio.freq_range[0] as f64);
         spin_freq_max  .set_value(set.audio.freq_range[1] as f64);
+        spin_frame     .set_value((set.frame.duration / 60) as f64);
+        spin_dot       .set_value(set.frame.dot_length as f64);
+        spin_resolution.set_value(set.fft.resolution as f64);
+        spin_overlap   .set_value(set.fft.overlap as f64);
+        check_bg       .set_active(set.fft.subtract_background);
+        spin_bg        .set_value(set.fft.background_percentile as f64);
+        check_blanker  .set_active(set.filter.blanker);
+        spin_blanker   .set_value(set.filter.blanker_threshold as f64);
+        check_notch    .set_active(set.filter.notch);
+        spin_notch     .set_value(set.filter.notch_threshold as f64);
+        label_scale    .set_text(&scale_text(&set));
         spin_brightness.set_value(set.image.brightness as f64);
         spin_contrast  .set_value(set.image.contrast as f64);
+        check_auto     .set_active(set.image.auto_level);
+        entry_palette  .set_text(set.image.palette.name());
+        palette_chooser.set_filename(&set.image.palette_file);
         check_win_xy   .set_active(set.image.use_window_xy);
         spin_width     .set_value(set.image.dimensions[0] as f64);
         spin_height    .set_value(set.image.dimensions[1] as f64);
         check_export   .set_active(set.export.export_enable);
+        check_annotate .set_active(set.export.annotate);
+        entry_callsign .set_text(&set.station.callsign);
+        entry_locator  .set_text(&set.station.locator);
+        spin_dial      .set_value(set.radio.dial_freq as f64);
+        entry_band     .set_text(&set.radio.band);
+        entry_sideband .set_text(match set.radio.sideband {
+            settings::Sideband::Usb => "USB",
+            settings::Sideband::Lsb => "LSB",
+        });
         check_single   .set_active(set.export.single);
         check_average  .set_active(set.export.average);
         check_peak     .set_active(set.export.peak);
@@ -127,9 +231,37 @@ pub (crate) fn build_gtk(
         entry_peak     .set_text(&set.names.peak);
         entry_hour     .set_text(&set.names.hour);
         entry_day      .set_text(&set.names.day);
-        file_chooser   .set_uri(&set.export.path.to_str().unwrap());
+        file_chooser   .set_filename(&set.export.path);
     }
 
+    // Live waterfall: keep newest frame from image thread, paint on demand
+    let frame: Rc<RefCell<Option<Pixbuf>>> = Rc::new(RefCell::new(None));
+
+    rx_img.attach(None, clone!(@strong frame, @strong waterfall => move |img| {
+        let (w, h) = img.dimensions();
+        let pb = Pixbuf::from_bytes(
+            &glib::Bytes::from_owned(img.into_raw()),
+            Colorspace::Rgb, false, 8,
+            w as i32, h as i32, (w * 3) as i32);
+        *frame.borrow_mut() = Some(pb);
+        waterfall.queue_draw();
+        glib::Continue(true)
+    }));
+
+    waterfall.connect_draw(clone!(@strong logger, @strong frame => move |area, cr| {
+        if let Some(pb) = &*frame.borrow() {
+            // stretch frame to fill the drawing area
+            cr.scale(
+                area.allocated_width()  as f64 / pb.width()  as f64,
+                area.allocated_height() as f64 / pb.height() as f64);
+            cr.set_source_pixbuf(pb, 0., 0.);
+            if let Err(e) = cr.paint() {
+                debug!(logger, "Waterfall paint error: {:?}", e);
+            }
+        }
+        Inhibit(false)
+    }));
+
     // Connect signals
     button_about.connect_clicked(clone!(@strong logger, @strong window_about
             => move |_| {
@@ -161,6 +293,44 @@ pub (crate) fn build_gtk(
         debug!(logger, "Selected rate: {}", set.audio.rate);
     }));
 
+    entry_format.connect_changed(clone!(@strong logger, @strong set,
+            @strong entry_format
+            => move |_| {
+        // Only hardcoded values available
+        let mut set = set.lock().unwrap();
+        set.audio.format = match entry_format.text().as_str() {
+            "u16" => settings::AudioFormat::u16,
+            "f32" => settings::AudioFormat::f32,
+            _     => settings::AudioFormat::i16,
+        };
+        debug!(logger, "Selected format: {:?}", set.audio.format);
+    }));
+
+    entry_window.connect_changed(clone!(@strong logger, @strong set,
+            @strong entry_window
+            => move |_| {
+        // Only hardcoded values available
+        if let Some(w) = settings::FftWindowType::from_name(entry_window.text().as_str()) {
+            let mut set = set.lock().unwrap();
+            set.fft.window = w;
+            debug!(logger, "Selected FFT window: {:?}", set.fft.window);
+        }
+    }));
+
+    check_iq.connect_toggled(clone!(@strong logger, @strong set,
+            @strong check_iq, @strong spin_freq_min, @strong spin_freq_max
+            => move |_| {
+        let min_freq = {
+            let mut set = set.lock().unwrap();
+            set.audio.iq = check_iq.is_active();
+            debug!(logger, "I/Q input: {:?}", set.audio.iq);
+            set.audio.min_freq()
+        };
+        // negative frequencies only exist in I/Q; clamping updates the range through the spins
+        spin_freq_min.set_range(min_freq as f64, 96000.);
+        spin_freq_max.set_range(min_freq as f64, 96000.);
+    }));
+
     check_export.connect_toggled(clone!(@strong logger, @strong set,
             @strong check_export
             => move |_| {
@@ -170,6 +340,81 @@ pub (crate) fn build_gtk(
     }));
 
     // EXPORT
+    check_annotate.connect_toggled(clone!(@strong logger, @strong set,
+            @strong check_annotate
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.export.annotate = check_annotate.is_active();
+        debug!(logger, "Annotate exports: {:?}", set.export.annotate);
+    }));
+
+    entry_callsign.connect_changed(clone!(@strong logger, @strong set,
+            @strong entry_callsign
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.station.callsign = entry_callsign.text().to_string();
+        debug!(logger, "Callsign: {:?}", set.station.callsign);
+    }));
+
+    entry_locator.connect_changed(clone!(@strong logger, @strong set,
+            @strong entry_locator
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.station.locator = entry_locator.text().to_string();
+        debug!(logger, "Locator: {:?}", set.station.locator);
+    }));
+
+    spin_dial.connect_value_changed(clone!(@strong logger, @strong set,
+            @strong spin_dial
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.radio.dial_freq = spin_dial.value() as u64;
+        debug!(logger, "Dial frequency: {} Hz", set.radio.dial_freq);
+    }));
+
+    entry_sideband.connect_changed(clone!(@strong logger, @strong set,
+            @strong entry_sideband, @strong spin_dial
+            => move |_| {
+        // Only hardcoded values available
+        let dial = {
+            let mut set = set.lock().unwrap();
+            let old = set.radio.sideband;
+            set.radio.sideband = match entry_sideband.text().as_str() {
+                "LSB" => settings::Sideband::Lsb,
+                _     => settings::Sideband::Usb,
+            };
+            debug!(logger, "Sideband: {:?}", set.radio.sideband);
+            // a dial left on the band preset follows the sideband
+            settings::band_preset(&set.radio.band)
+                .filter(|b| b.dial(old) == set.radio.dial_freq)
+                .map(|b| b.dial(set.radio.sideband))
+        };
+        if let Some(d) = dial {
+            spin_dial.set_value(d as f64);
+        }
+    }));
+
+    // any band name is shown on exports; a preset also fills in dial and frequency range through
+    //   their widgets, for the sideband selected
+    entry_band.connect_changed(clone!(@strong logger, @strong set,
+            @strong entry_band,
+            @strong spin_dial, @strong spin_freq_min, @strong spin_freq_max
+            => move |_| {
+        let sideband = {
+            let mut set = set.lock().unwrap();
+            set.radio.band = entry_band.text().to_string();
+            debug!(logger, "Band: {:?}", set.radio.band);
+            set.radio.sideband
+        };
+        if let Some(band) = settings::band_preset(entry_band.text().as_str()) {
+            debug!(logger, "Band preset: {:?}", band);
+            let range = band.freq_range();
+            spin_dial     .set_value(band.dial(sideband) as f64);
+            spin_freq_min .set_value(range[0] as f64);
+            spin_freq_max .set_value(range[1] as f64);
+        }
+    }));
+
     check_single.connect_toggled(clone!(@strong logger, @strong set,
             @strong check_single
             => move |_| {
@@ -273,8 +518,8 @@ pub (crate) fn build_gtk(
             => move |_| {
         let mut set = set.lock().unwrap();
         set.audio.freq_range = vec![
-            spin_freq_min.value()  as u32,
-            set.audio.freq_range[1] as u32];
+            spin_freq_min.value()  as i32,
+            set.audio.freq_range[1]];
         debug!(logger, "Set frequency range: {:?}", set.audio.freq_range);
     }));
 
@@ -283,11 +528,94 @@ pub (crate) fn build_gtk(
             => move |_| {
         let mut set = set.lock().unwrap();
         set.audio.freq_range = vec![
-            set.audio.freq_range[0] as u32,
-            spin_freq_max.value()  as u32];
+            set.audio.freq_range[0],
+            spin_freq_max.value()  as i32];
         debug!(logger, "Set frequency range: {:?}", set.audio.freq_range);
     }));
 
+    // FRAME
+    spin_frame.connect_value_changed(clone!(@strong logger, @strong set,
+            @strong spin_frame
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.frame.duration = spin_frame.value() as u32 * 60;
+        debug!(logger, "Frame duration: {}s", set.frame.duration);
+    }));
+
+    spin_dot.connect_value_changed(clone!(@strong logger, @strong set,
+            @strong spin_dot
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.frame.dot_length = spin_dot.value() as u32;
+        debug!(logger, "Dot length: {}s", set.frame.dot_length);
+    }));
+
+    // FFT
+    spin_resolution.connect_value_changed(clone!(@strong logger, @strong set,
+            @strong spin_resolution
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.fft.resolution = spin_resolution.value() as f32;
+        debug!(logger, "FFT resolution: {} Hz/bin", set.fft.resolution);
+    }));
+
+    spin_overlap.connect_value_changed(clone!(@strong logger, @strong set,
+            @strong spin_overlap
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.fft.overlap = spin_overlap.value() as u8;
+        debug!(logger, "FFT overlap: {}%", set.fft.overlap);
+    }));
+
+    check_bg.connect_toggled(clone!(@strong logger, @strong set,
+            @strong check_bg
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.fft.subtract_background = check_bg.is_active();
+        debug!(logger, "Subtract background: {:?}", set.fft.subtract_background);
+    }));
+
+    spin_bg.connect_value_changed(clone!(@strong logger, @strong set,
+            @strong spin_bg
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.fft.background_percentile = spin_bg.value() as u8;
+        debug!(logger, "Background percentile: {}", set.fft.background_percentile);
+    }));
+
+    // FILTER
+    check_blanker.connect_toggled(clone!(@strong logger, @strong set,
+            @strong check_blanker
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.filter.blanker = check_blanker.is_active();
+        debug!(logger, "Impulse blanker: {:?}", set.filter.blanker);
+    }));
+
+    spin_blanker.connect_value_changed(clone!(@strong logger, @strong set,
+            @strong spin_blanker
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.filter.blanker_threshold = spin_blanker.value() as u8;
+        debug!(logger, "Blanker threshold: {} dB", set.filter.blanker_threshold);
+    }));
+
+    check_notch.connect_toggled(clone!(@strong logger, @strong set,
+            @strong check_notch
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.filter.notch = check_notch.is_active();
+        debug!(logger, "Carrier notch: {:?}", set.filter.notch);
+    }));
+
+    spin_notch.connect_value_changed(clone!(@strong logger, @strong set,
+            @strong spin_notch
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.filter.notch_threshold = spin_notch.value() as u8;
+        debug!(logger, "Notch threshold: {} dB", set.filter.notch_threshold);
+    }));
+
     spin_brightness.connect_value_changed(clone!(@strong logger, @strong set,
             @strong spin_brightness
             => move |_| {
@@ -304,6 +632,14 @@ pub (crate) fn build_gtk(
         debug!(logger, "Contrast: {}", set.image.contrast);
     }));
 
+    check_auto.connect_toggled(clone!(@strong logger, @strong set,
+            @strong check_auto
+            => move |_| {
+        let mut set = set.lock().unwrap();
+        set.image.auto_level = check_auto.is_active();
+        debug!(logger, "Auto-level: {:?}", set.image.auto_level);
+    }));
+
     check_win_xy.connect_toggled(clone!(@strong logger, @strong set,
             @strong check_win_xy
             => move |_| {
@@ -312,20 +648,62 @@ pub (crate) fn build_gtk(
         debug!(logger, "Use window dimensions: {}", set.image.use_window_xy);
     }));
 
+    // (re)read the custom gradient whenever it may have changed
+    let load_palette = Rc::new(clone!(@strong logger, @strong set => move || {
+        let mut set = set.lock().unwrap();
+        if set.image.palette == settings::Palette::Custom {
+            match settings::load_gradient(&set.image.palette_file) {
+                Ok(g) => set.image.gradient = g,
+                Err(e) => error!(logger, "Error reading palette {:?}: {:?}", set.image.palette_file, e),
+            }
+        }
+    }));
+
+    entry_palette.connect_changed(clone!(@strong logger, @strong set,
+            @strong load_palette,
+            @strong entry_palette
+            => move |_| {
+        // Only hardcoded values available
+        if let Some(p) = settings::Palette::from_name(entry_palette.text().as_str()) {
+            set.lock().unwrap().image.palette = p;
+            debug!(logger, "Selected palette: {:?}", p);
+            load_palette();
+        }
+    }));
+
+    palette_chooser.connect_file_set(clone!(
+            @strong logger, @strong set,
+            @strong load_palette,
+            @strong palette_chooser
+            => move |_| {
+        if let Some(path) = palette_chooser.filename() {
+            debug!(logger, "Palette file: {:?}", path);
+            set.lock().unwrap().image.palette_file = path;
+            load_palette();
+        }
+    }));
+
     file_chooser.connect_file_set(clone!(
             @strong logger, @strong set,
             @strong file_chooser
             => move |_| {
-        let mut set = set.lock().unwrap();
-        // TODO: unwrap()
-        set.export.path = PathBuf::from_str(&file_chooser.uri().unwrap()).unwrap();
-        debug!(logger, "File save path: {:?}", set.export.path)
+        if let Some(path) = file_chooser.filename() {
+            let mut set = set.lock().unwrap();
+            set.export.path = path;
+            debug!(logger, "File save path: {:?}", set.export.path)
+        }
     }));
 
+    // settings as they were when the popover opened, to tell what changed at close
+    let set_opened: Rc<RefCell<Option<settings::Settings>>> = Rc::new(RefCell::new(None));
+
     window_settings.connect_show(clone!(@strong logger,
+            @strong set,
+            @strong set_opened,
             @strong list_devices
             => move |_| {
         debug!(logger, "Settings opened");
+        *set_opened.borrow_mut() = Some(set.lock().unwrap().clone());
         list_devices.clear();
         let host = cpal::default_host();
         let c_devices: Vec<cpal::Device> = host.devices().unwrap().collect();
@@ -336,17 +714,44 @@ pub (crate) fn build_gtk(
         }
     }));
 
-    // save prefs at popover close
+    // keep resulting scale up to date; connected after the handlers above, so it sees their changes
+    let update_scale = Rc::new(clone!(@strong set, @strong label_scale => move || {
+        label_scale.set_text(&scale_text(&set.lock().unwrap()));
+    }));
+    for spin in &[&spin_freq_min, &spin_freq_max, &spin_frame, &spin_dot,
+                  &spin_width, &spin_height, &spin_resolution, &spin_overlap] {
+        spin.connect_value_changed(clone!(@strong update_scale => move |_| update_scale()));
+    }
+    entry_rate.connect_changed(clone!(@strong update_scale => move |_| update_scale()));
+
+    // apply prefs at popover close: capture settings need a new stream, which also rebuilds the
+    //   FFT; anything else only rebuilds the FFT
     window_settings.connect_closed(clone!(
             @strong logger,
             @strong set,
+            @strong set_opened,
+            @strong fft_restart,
             @strong cvar_stream_2
             => move |_| {
         debug!(logger, "Prefs closed");
-        {
+        let old = match set_opened.borrow_mut().take() {
+            Some(old) => old,
+            None => return,
+        };
+        let new = set.lock().unwrap().clone();
+
+        let (a, b) = (&old.audio, &new.audio);
+        if a.device != b.device || a.rate != b.rate || a.format != b.format || a.iq != b.iq {
+            debug!(logger, "Capture settings changed, restarting stream");
             let (lock, cvar) = &*cvar_stream_2;
             *lock.lock().unwrap() = true;
             cvar.notify_one();
+        } else if old.fft != new.fft || old.filter != new.filter || old.frame != new.frame
+                || a.freq_range != b.freq_range
+                // history keeps one column per pixel of image width
+                || old.image.dimensions != new.image.dimensions {
+            debug!(logger, "Processing settings changed, rebuilding FFT");
+            *fft_restart.lock().unwrap() = true;
         }
     }));
 
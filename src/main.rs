@@ -12,6 +12,16 @@ mod gui;
 mod settings;
 mod windows;
 mod logging;
+mod render;
+mod spectrum;
+mod export;
+mod source;
+mod record;
+mod archive;
+mod analyzer;
+mod replay;
+mod font;
+mod annotate;
 
 #[macro_use]
 extern crate slog;
@@ -20,24 +30,65 @@ extern crate slog;
 use std::sync::{mpsc, Arc, Mutex, Condvar};
 use std::thread;
 
+// Time
+use chrono::{DateTime, Utc};
+
 // Audio
 use cpal;
 use cpal::traits::*;
 
+// GUI
+use glib;
+
 // Image
 use image;
 use colorous;
 
-// Data processing
-use rustfft::{
-    FftPlanner,
-    num_complex::Complex,
-};
 
+/// Messages from the audio capture thread to the FFT thread
+enum Capture<T> {
+    /// A stream started; samples that follow are counted from this instant
+    Start(DateTime<Utc>),
+    Samples(Vec<T>),
+}
 
-// remain generic to use any available sample format from cpal
-fn send_samples<T: cpal::Sample>(s: &[T], tx: &mpsc::Sender<Vec<T>>) {
-    tx.send(Vec::from(s));
+// remain generic to use any available sample format from cpal; processing is all f32
+fn send_samples<T: cpal::Sample>(s: &[T], tx: &mpsc::Sender<Capture<f32>>, tx_rec: &Option<mpsc::Sender<Capture<f32>>>) {
+    let d: Vec<f32> = s.iter().map(|x| x.to_f32()).collect();
+    if let Some(tx_rec) = tx_rec {
+        tx_rec.send(Capture::Samples(d.clone()));
+    }
+    tx.send(Capture::Samples(d));
+}
+
+/// Check that `dev` can capture `wanted` samples at this channel count and rate; the error lists
+/// what it supports instead
+fn supported_format(
+    dev: &cpal::Device,
+    channels: cpal::ChannelCount,
+    rate: u32,
+    wanted: cpal::SampleFormat,
+) -> Result<cpal::SampleFormat, String> {
+    let configs: Vec<cpal::SupportedStreamConfigRange> = dev.supported_input_configs()
+        .map_err(|e| format!("Error reading device configs: {:?}", e))?
+        .collect();
+    let usable: Vec<cpal::SampleFormat> = configs.iter()
+        .filter(|c| c.channels() == channels
+            && c.min_sample_rate().0 <= rate
+            && c.max_sample_rate().0 >= rate)
+        .map(|c| c.sample_format())
+        .collect();
+
+    if usable.contains(&wanted) {
+        Ok(wanted)
+    } else {
+        let list: Vec<String> = configs.iter()
+            .map(|c| format!("{}ch {:?} {}-{} Hz",
+                c.channels(), c.sample_format(), c.min_sample_rate().0, c.max_sample_rate().0))
+            .collect();
+        Err(format!("Device can't capture {} channel(s) of {:?} at {} Hz. Supported: {}",
+            channels, wanted, rate, list.join(", ")))
+    }
 }
 
 fn main() {
@@ -46,26 +97,62 @@ fn main() {
 
     // Read settings
     let opts = settings::clap_args();
-    let set = Arc::new(Mutex::new(settings::Settings::default()));
-    /*
+    let mut settings = settings::Settings::default();
     if let Some(c) = opts.value_of("config") {
-        let mut set = set.lock().unwrap();
-        set.config = c.into();
+        settings.config = (*shellexpand::full(c).unwrap()).into();
     }
-    {
-        let mut set = set.lock().unwrap();
-
-        match set.load_config(&opts) {
-            Ok(s) => *set = s,
-            Err(e) => error!(logger, "Error loading config:\n{:?}", e),
+    match settings.create_config() {
+        Ok(true)  => info!(logger, "Created default config: {:?}", settings.config),
+        Ok(false) => {},
+        Err(e)    => error!(logger, "Error creating config:\n{:?}", e),
+    }
+    // running on defaults would drop every argument, and saving them would wipe the config file
+    match settings.load_config(&opts, &logger) {
+        Ok(s)  => settings = s,
+        Err(e) => {
+            error!(logger, "Error loading config:\n{:?}", e);
+            // dropping the only logger flushes it before exiting
+            drop(logger);
+            std::process::exit(1);
+        },
+    }
+    if opts.is_present("save_prefs") {
+        if let Err(e) = settings.write_config() {
+            error!(logger, "Error writing config:\n{:?}", e);
         }
-        if opts.is_present("save_prefs") {
-            if set.write_config().is_err() {
-                error!(logger, "Error writing config");
-            }
+    }
+    // re-render archived spectra or recordings and quit, without audio hardware or GUI
+    if let Some(job) = settings::render_job(&opts) {
+        match replay::render(&job, &settings, &logger) {
+            Ok(path) => info!(logger, "Rendered {:?}", path),
+            Err(e)   => error!(logger, "Render failed: {:?}", e),
         }
+        return;
     }
-    */
+    // recordings are processed at their native rate; stereo is I/Q only when asked for
+    if let Some(path) = &settings.audio.input_file {
+        match source::probe(path) {
+            Ok(info) => {
+                settings.audio.rate = info.sample_rate;
+                if settings.audio.iq && info.channels != 2 {
+                    warn!(logger, "I/Q input needs a stereo file, got {} channel(s); processing as audio", info.channels);
+                    settings.audio.iq = false;
+                }
+            },
+            Err(e)   => error!(logger, "Error reading input file:\n{:?}", e),
+        }
+    }
+    // only I/Q has negative frequencies; archives rendered above carry their own I/Q flag
+    let freq_range = settings.audio.freq_range.clone();
+    if settings.audio.clamp_freq_range() {
+        warn!(logger, "Frequency range {:?} needs I/Q input (--iq), using {:?}", freq_range, settings.audio.freq_range);
+    }
+    // spectral history of the frame being captured, one column per pixel
+    let history = Arc::new(Mutex::new(spectrum::History::new(
+        settings.frame.duration, settings.image.dimensions[0], &settings.audio.freq_range)));
+    // signaled whenever the image thread takes finished frames out of the history
+    let history_taken = Arc::new(Condvar::new());
+    let set = Arc::new(Mutex::new(settings));
 
     // audio data channel to FFT process thread
     let (tx, rx) = mpsc::channel();
@@ -77,10 +164,83 @@ fn main() {
     // FFT signaling to image thread
     let cvar_fft_img_src = Arc::new((Mutex::new(false), Condvar::new()));
     let cvar_fft_img_dest = cvar_fft_img_src.clone();
-    let mut buffer_proc_lrg: Arc<Mutex<Vec<Vec<f32>>>> = Arc::new(Mutex::new(Vec::new()));                                    // buffer for whole time slot
 
     let quit_condition: Arc<Mutex<bool>> = Arc::new(Mutex::new(false));
-    gui::build_gtk(Arc::clone(&set), &logger, cvar_ui_stream_src, Arc::clone(&quit_condition));
+    let fft_done: Arc<Mutex<bool>> = Arc::new(Mutex::new(false));
+    let fft_restart: Arc<Mutex<bool>> = Arc::new(Mutex::new(false));
+
+    // rendered frames from image thread to GUI main loop; none without a GUI
+    let headless = opts.is_present("headless");
+    let tx_img = if headless {
+        // quit on SIGINT/SIGTERM/SIGHUP the way closing the main window does
+        let res = ctrlc::set_handler(mclone!(logger, quit_condition, cvar_ui_stream_src => move || {
+            info!(logger, "Signal received, quitting...");
+            *quit_condition.lock().unwrap() = true;
+
+            let (lock, cvar) = &*cvar_ui_stream_src;
+            *lock.lock().unwrap() = true;
+            cvar.notify_one();
+        }));
+        if let Err(e) = res {
+            error!(logger, "Error setting signal handler: {:?}", e);
+        }
+        None
+    } else {
+        let (tx_img, rx_img) = glib::MainContext::channel::<image::RgbImage>(glib::PRIORITY_DEFAULT);
+        gui::build_gtk(
+            Arc::clone(&set), &logger, cvar_ui_stream_src, Arc::clone(&fft_restart),
+            Arc::clone(&quit_condition), rx_img);
+        Some(tx_img)
+    };
+
+    // raw audio to recorder thread, when enabled
+    let record = set.lock().unwrap().record.clone();
+    let (tx_rec, thread_record) = if record.enable {
+        let (tx_rec, rx_rec) = mpsc::channel::<Capture<f32>>();
+        let thread_record = thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(mclone!(logger, set => move || {
+                let logger = logger.new(o!("thread" => format!("{}", thread::current().name().unwrap())));
+
+                // frame duration and audio format may change between streams
+                let stream = |set: &settings::Settings| {
+                    let period = match record.rotate {
+                        settings::RecordRotation::Frame => set.frame.duration,
+                        settings::RecordRotation::Hour  => 3600,
+                    };
+                    (period, set.audio.rate, if set.audio.iq { 2 } else { 1 })
+                };
+                let (period, rate, channels) = stream(&set.lock().unwrap());
+                let mut recorder = record::Recorder::new(&record.path, period, rate, channels);
+
+                for msg in &rx_rec {
+                    let res = match msg {
+                        Capture::Start(t) => {
+                            let (period, rate, channels) = stream(&set.lock().unwrap());
+                            recorder.start(t, period, rate, channels).map(|f| f.into_iter().collect())
+                        },
+                        Capture::Samples(d) => recorder.write(&d),
+                    };
+                    match res {
+                        Ok(files) => for f in files {
+                            info!(logger, "Recorded {:?}", f);
+                        },
+                        Err(e) => error!(logger, "Recording failed: {:?}", e),
+                    }
+                }
+
+                // stream closed for good
+                match recorder.finish() {
+                    Ok(Some(f)) => info!(logger, "Recorded {:?}", f),
+                    Ok(None)    => {},
+                    Err(e)      => error!(logger, "Recording failed: {:?}", e),
+                }
+                debug!(logger, "breaking recorder thread");
+            }));
+        (Some(tx_rec), Some(thread_record))
+    } else {
+        (None, None)
+    };
 
     let thread_audio = thread::Builder::new()
         .name("audio_capture".to_string())
@@ -89,12 +249,16 @@ fn main() {
 
             'restart_loop: loop {
                 let tx = tx.clone();
+                let tx_rec = tx_rec.clone();
                 let (lock, cvar) = &*cvar_ui_stream_dest;
 
                 let set = set.lock().unwrap();
                 let dev_name = &set.audio.device.clone();
-                // TODO: hardcoded channel count - only good for SSB audio (not IQ)
-                let channels: cpal::ChannelCount = 1;
+                let input_file = set.audio.input_file.clone();
+                let iq = set.audio.iq;
+                let format = set.audio.format;
+                // mono for SSB audio, stereo I/Q pairs from SDR baseband
+                let channels: cpal::ChannelCount = if iq { 2 } else { 1 };
                 let cfg = cpal::StreamConfig {
                     channels,
                     sample_rate: cpal::SampleRate(set.audio.rate),
@@ -104,7 +268,38 @@ fn main() {
                 // unlock settings
                 drop(set);
 
+                if let Some(path) = input_file {
+                    info!(logger, "Input file: {:?}", path);
+                    match source::probe(&path) {
+                        Ok(file) => {
+                            // I/Q was cleared at startup unless the file is stereo
+                            tx.send(Capture::Start(file.start_time(&path)));
+                            let res = source::read_file(&path, |d| {
+                                if iq {
+                                    tx.send(Capture::Samples(d.to_vec()));
+                                } else {
+                                    tx.send(Capture::Samples(source::downmix(d, file.channels)));
+                                }
+                            });
+                            match res {
+                                Ok(_)  => info!(logger, "Input file finished"),
+                                Err(e) => error!(logger, "{:?}", e),
+                            }
+                        },
+                        Err(e) => error!(logger, "{:?}", e),
+                    }
+
+                    // nothing left to capture; without a GUI to look at, finish right away
+                    let mut restart = lock.lock().unwrap();
+                    while !headless && !*quit_condition.lock().unwrap() {
+                        restart = cvar.wait(restart).unwrap();
+                    }
+                    debug!(logger, "breaking stream thread");
+                    break 'restart_loop
+                }
+
                 let host = cpal::default_host();
+                let mut started = false;
 
                 // TODO: Error handling
                 if let Ok(in_devices) = host.input_devices() {
@@ -113,35 +308,72 @@ fn main() {
                         .collect();
                     if let Some(dev) = devs.get(0) {
                         info!(logger, "Device: {}", dev.name().unwrap());
-                        let log_inner = logger.new(o!("thread" => format!("{}", thread::current().name().unwrap())));
-                        if let Ok(stream) = dev.build_input_stream(
-                            &cfg,
-                            move |data, _cb| {
-                                send_samples::<f32>(data, &tx);
-                            },
-                            move |error| {
-                                debug!(log_inner, "{:?}", error);
-                                // TODO: How to handle stream error: error popup, stop stream, exit?
-                            },
-                        ) {
-                            match stream.play() {
-                                Ok(_) => {
-                                    // Thread sleep must be in same block as `stream.play()`
-                                    // to keep `stream` from going out of scope and closing
-                                    let mut restart = lock.lock().unwrap();
-                                    *restart = false;
-                                    while !*restart {
-                                        restart = cvar.wait(restart).unwrap();
-                                    }
-                                },
-                                Err(e) => {
-                                    error!(logger, "{:?}", e);
+                        match supported_format(dev, channels, cfg.sample_rate.0, format.into()) {
+                            Ok(fmt) => {
+                                let tx_start = tx.clone();
+                                let tx_rec_start = tx_rec.clone();
+                                let log_inner = logger.new(o!("thread" => format!("{}", thread::current().name().unwrap())));
+                                let err_fn = move |error| {
+                                    debug!(log_inner, "{:?}", error);
                                     // TODO: How to handle stream error: error popup, stop stream, exit?
-                                },
-                            }
-                        }  // Ok(stream)
+                                };
+                                let stream = match fmt {
+                                    cpal::SampleFormat::I16 => dev.build_input_stream(&cfg,
+                                        move |data: &[i16], _cb| send_samples(data, &tx, &tx_rec), err_fn),
+                                    cpal::SampleFormat::U16 => dev.build_input_stream(&cfg,
+                                        move |data: &[u16], _cb| send_samples(data, &tx, &tx_rec), err_fn),
+                                    cpal::SampleFormat::F32 => dev.build_input_stream(&cfg,
+                                        move |data: &[f32], _cb| send_samples(data, &tx, &tx_rec), err_fn),
+                                    // formats added by newer cpal versions can't be converted yet
+                                    #[allow(unreachable_patterns)]
+                                    other => {
+                                        error!(logger, "Unsupported sample format: {:?}", other);
+                                        Err(cpal::BuildStreamError::StreamConfigNotSupported)
+                                    },
+                                };
+                                match stream {
+                                    Ok(stream) => {
+                                        // anchor sample count for column timestamps
+                                        let now = Utc::now();
+                                        tx_start.send(Capture::Start(now));
+                                        if let Some(tx_rec) = &tx_rec_start {
+                                            tx_rec.send(Capture::Start(now));
+                                        }
+                                        match stream.play() {
+                                            Ok(_) => {
+                                                // Thread sleep must be in same block as `stream.play()`
+                                                // to keep `stream` from going out of scope and closing
+                                                started = true;
+                                                let mut restart = lock.lock().unwrap();
+                                                *restart = false;
+                                                while !*restart {
+                                                    restart = cvar.wait(restart).unwrap();
+                                                }
+                                            },
+                                            Err(e) => {
+                                                error!(logger, "{:?}", e);
+                                                // TODO: How to handle stream error: error popup, stop stream, exit?
+                                            },
+                                        }
+                                    },
+                                    Err(e) => error!(logger, "Error opening stream: {:?}", e),
+                                }
+                            },
+                            Err(msg) => error!(logger, "{}", msg),
+                        }
+                    } else {
+                        error!(logger, "Device not found: {}", dev_name);
                     }  // Some(dev)
                 }  // Ok(in_devices)
+
+                // capture failed: retry only once settings change instead of spinning
+                if !started {
+                    let mut restart = lock.lock().unwrap();
+                    *restart = false;
+                    while !*restart && !*quit_condition.lock().unwrap() {
+                        restart = cvar.wait(restart).unwrap();
+                    }
+                }
                 if *quit_condition.lock().unwrap() {
                     debug!(logger, "breaking stream thread");
                     break 'restart_loop
@@ -151,128 +383,182 @@ fn main() {
 
     let thread_fft = thread::Builder::new()
         .name("fft_process".to_string())
-        .spawn(mclone!(logger, set, buffer_proc_lrg => move || {
+        .spawn(mclone!(logger, set, fft_done, fft_restart, history, history_taken => move || {
             // constantly receiving data, notify image gen thread upon new processed data
             let logger = logger.new(o!("thread" => format!("{}", thread::current().name().unwrap())));
 
-            'outer: loop {
-                // get settings
-                let set = set.lock().unwrap();
-
-                let (img_x, img_y) = (set.image.dimensions[0], set.image.dimensions[1]);
-                let (freq_min, freq_max) = (set.audio.freq_range[0], set.audio.freq_range[1]);
-                let window = &set.fft_window.clone();
-                let sample_rate = set.audio.rate;
+            // recordings are read faster than real time: wait for the image thread to take
+            //   finished frames instead of dropping them
+            let throttle = set.lock().unwrap().audio.input_file.is_some();
+            let warn_dropped = |hist: &mut spectrum::History| {
+                let dropped = hist.take_dropped();
+                if dropped > 0 {
+                    warn!(logger, "Image thread falling behind, dropped {} finished frame(s)", dropped);
+                }
+            };
 
-                // unlock settings ASAP and do heavy work after
-                drop(set);
+            // FFT columns to hourly archive files, when enabled
+            let archive = set.lock().unwrap().archive.clone();
+            let mut archiver = match archive.enable {
+                true  => Some(archive::Archiver::new(&archive.path)),
+                false => None,
+            };
 
-                // TODO: time frame from where? hardcoded to 2 minute window for now
-                let samples_per_frame = 2_u32 * 60 * sample_rate;
-
-                let samples_per_pixel_x = samples_per_frame / img_x;
-
-                let overlap_percent: f32 = 0.33;
-                let overlap_samples = (samples_per_pixel_x as f32 * overlap_percent).round() as u32;
-
-                let window_size: u32 = samples_per_pixel_x + (overlap_samples * 2_u32);
-                let shift_size = window_size - overlap_samples;
-
-                let nearest_pow_2: u32 = ((window_size as f32).ln() / 2_f32.ln()).ceil() as u32;
-                let fft_size = 2_u32.pow(nearest_pow_2);
-
-                // sample frequency ranges
-                // most likely in drawing thread
-                let freq_per_fft_samp = (sample_rate / 2) / (fft_size / 2 - 1);
-                let sample_first = freq_per_fft_samp * freq_min;
-                let sample_last  = freq_per_fft_samp * freq_max;
-                let _samples_per_pixel_y = (sample_last - sample_first) / img_y;
-
-                let mut buffer_proc: Vec<Complex<f32>> = Vec::with_capacity(fft_size as usize);         // buffer for windowed and FFT processed samples
-                let mut buffer_raw:  Vec<f32>          = Vec::with_capacity(window_size as usize);      // buffer for unwindowed, unprocessed samples
-                let mut fft_scratch: Vec<Complex<f32>> = vec![Complex::new(0., 0.); fft_size as usize]; // scratch for fft processor
-
-                let mut planner = FftPlanner::new();
-                let fft = planner.plan_fft_forward(fft_size as usize);
-
-                for d in &rx {
-                    // sample processing
-                    for s in d {
-                        buffer_raw.push(s);
-                        if buffer_raw.len() >= window_size as usize {
-                            buffer_proc.clear();
-                            buffer_proc.append(
-                                &mut buffer_raw.iter()
-                                    .zip(&window.window_func)
-                                    .map(|x| Complex::from(*x.0 * x.1))
-                                    .collect());
-
-                            // zero padding to increase FFT resolution
-                            buffer_proc.extend(vec![Complex::new(0., 0.); (fft_size - window_size) as usize]);
-
-                            // FFT processing
-                            fft.process_with_scratch(&mut buffer_proc, &mut fft_scratch);
-
-                            // discard all frequencies after Nyquist - powers of 2 always produce
-                            //   an even number of samples
-                            // N/2 for even number of input points (exactly Nyquist freq)
-                            // (N-1)/2 for odd (last positive point)
-                            buffer_proc.truncate(fft_size as usize / 2);
-
-                            // normalize processed FFT samples
-                            let mut buf_lock = buffer_proc_lrg.lock().unwrap();
-                            buf_lock.push(
-                                buffer_proc.iter().map(|x| x.norm() / (fft_size as f32).sqrt()
-                            ).collect());
-                            drop(buf_lock);
-
-                            // shift left window_size - overlap_samples and leave tail samples
-                            buffer_raw.rotate_left(shift_size as usize);
-                            buffer_raw.truncate(overlap_samples as usize);
+            // (re)build the FFT chain with current settings
+            let build = |a: Option<&analyzer::Analyzer>, anchor: DateTime<Utc>| {
+                let set = set.lock().unwrap();
+                // frame layout changes finish the frame being captured
+                let mut hist = history.lock().unwrap();
+                hist.configure(set.frame.duration, set.image.dimensions[0], &set.audio.freq_range);
+                warn_dropped(&mut hist);
+                drop(hist);
+                let analyzer = match a {
+                    // the stream keeps running, and so does its timeline
+                    Some(a) => a.rebuild(&set),
+                    None    => analyzer::Analyzer::new(&set, anchor),
+                };
+                let plan = *analyzer.plan();
+                debug!(logger, "FFT plan: {:?}, {} Hz/bin, {} columns/s",
+                    plan, plan.hz_per_bin(set.audio.rate), set.audio.rate as f32 / plan.shift_size as f32);
+                (analyzer, set.audio.freq_range.clone())
+            };
+            let (mut analyzer, mut freq_range) = build(None, Utc::now());
+
+            for msg in &rx {
+                match msg {
+                    Capture::Start(t) => {
+                        // new stream, possibly at a new rate: old samples are not contiguous
+                        //   with the next ones
+                        let (a, f) = build(None, t);
+                        analyzer = a;
+                        freq_range = f;
+                    },
+                    Capture::Samples(d) => {
+                        let (plan, window_type) = (*analyzer.plan(), analyzer.window_type());
+                        analyzer.process(&d, |column| {
+                            if let Some(a) = archiver.as_mut() {
+                                let layout = archive::Layout::new(archive.format, window_type, &plan, &column, &freq_range);
+                                match a.write(layout, &column) {
+                                    Ok(Some(path)) => info!(logger, "Archived {:?}", path),
+                                    Ok(None)       => {},
+                                    Err(e)         => error!(logger, "Archive failed: {:?}", e),
+                                }
+                            }
+                            let mut hist = history.lock().unwrap();
+                            while throttle && hist.is_full() {
+                                hist = history_taken.wait(hist).unwrap();
+                            }
+                            hist.push(column);
+                            warn_dropped(&mut hist);
+                            drop(hist);
 
                             // notify image processor
                             let (lock, cvar) = &*cvar_fft_img_src;
                             let mut start = lock.lock().unwrap();
                             *start = true;
                             cvar.notify_one();
-                        }
-                    }
+                        });
+                    },
+                }
 
-                    // rebuild FFT chain when audio settings change
-                    // if settings_change {
-                    //   continue 'outer
-                    // }
+                // rebuild FFT chain when processing settings change; the stream keeps running
+                if std::mem::replace(&mut *fft_restart.lock().unwrap(), false) {
+                    debug!(logger, "rebuilding FFT");
+                    let (a, f) = build(Some(&analyzer), Utc::now());
+                    analyzer = a;
+                    freq_range = f;
                 }
-                // accessible when rx.iter() returns None, which only happens when Sender is dropped
-                // otherwise 'outer is explicitly restarted
-                debug!(logger, "breaking fft thread");
-                break 'outer;
             }
+            // rx.iter() returns None only when the Sender is dropped
+            debug!(logger, "breaking fft thread");
+
+            match archiver.as_mut().map(|a| a.finish()) {
+                Some(Ok(Some(path))) => info!(logger, "Archived {:?}", path),
+                Some(Err(e))         => error!(logger, "Archive failed: {:?}", e),
+                _                    => {},
+            }
+
+            // no more data: let image thread save what it has and quit
+            *fft_done.lock().unwrap() = true;
+            let (lock, cvar) = &*cvar_fft_img_src;
+            *lock.lock().unwrap() = true;
+            cvar.notify_one();
     }));
 
     let thread_image = thread::Builder::new()
         .name("image".to_string())
-        .spawn(mclone!(logger, quit_condition, buffer_proc_lrg => move || {
-            // wait until data to process is available, send render update to gui(or another place?)
+        .spawn(mclone!(logger, set, fft_done, history, history_taken => move || {
+            // wait until data to process is available, send rendered frame to GUI
             let logger = logger.new(o!("thread" => format!("{}", thread::current().name().unwrap())));
             debug!(logger, "image thread");
 
+            // images built from finished frames
+            let mut exporter = export::Exporter::new();
+
             loop {
                 let (lock, cvar) = &*cvar_fft_img_dest;
                 let mut start = lock.lock().unwrap();
                 while !*start {
                     start = cvar.wait(start).unwrap();
                 }
+                // reset and release signal so FFT thread isn't blocked while drawing
+                *start = false;
+                drop(start);
 
-                // create image canvas w/dimensions
+                // all captured data is processed once the FFT thread is done
+                let quit = *fft_done.lock().unwrap();
 
-                let buf_lock = buffer_proc_lrg.lock().unwrap();
-                // img drawing here
-                drop(buf_lock);
+                let set = set.lock().unwrap();
+                let image = set.image.clone();
+                let freq_range = set.audio.freq_range.clone();
+                let inverted = set.radio.inverted();
+                let export = set.export.clone();
+                let names = set.names.clone();
+                let labels = annotate::Labels::new(&set);
+                drop(set);
 
-                // send image to GUI
+                // take snapshots and render without holding the history
+                let mut hist = history.lock().unwrap();
+                let mut finished = hist.take_finished();
+                let current = hist.current();
+                // at quit, the frame being captured is saved as it is
+                if quit {
+                    finished.extend(hist.take_current());
+                }
+                drop(hist);
+                history_taken.notify_one();
 
-                if *quit_condition.lock().unwrap() {
+                // draw the frame being captured
+                let img = match (&tx_img, &current) {
+                    (Some(_), Some(frame)) => Some(render::waterfall(frame, &freq_range, inverted, &image)),
+                    _ => None,
+                };
+                let grids: Vec<render::Grid> = finished.iter()
+                    .map(|frame| render::grid(frame, &freq_range, &image.dimensions, inverted))
+                    .collect();
+
+                let mut written = Vec::new();
+                for grid in grids {
+                    written.extend(exporter.finish_frame(grid, &image, &export, &names, &labels));
+                }
+                if quit {
+                    written.extend(exporter.flush(&image, &export, &names, &labels));
+                }
+                for r in written {
+                    match r {
+                        Ok(path) => info!(logger, "Exported {:?}", path),
+                        Err(e)   => error!(logger, "Export failed: {:?}", e),
+                    }
+                }
+
+                // send image to GUI; fails only once the GUI is gone
+                if let (Some(tx_img), Some(img)) = (&tx_img, img) {
+                    if tx_img.send(img).is_err() {
+                        debug!(logger, "GUI closed, frame dropped");
+                    }
+                }
+
+                if quit {
                     debug!(logger, "breaking img thread");
                     break;
                 }
@@ -283,6 +569,7 @@ fn main() {
     threads.push(thread_audio);
     threads.push(thread_fft);
     threads.push(thread_image);
+    threads.extend(thread_record);
 
     // tx, rx
     //      tx -> audio capture thread
@@ -293,7 +580,9 @@ fn main() {
     // who controls timeframe: render thread, main thread?
     // always render to window size, save image files at chosen resolution
 
-    gtk::main();
+    if !headless {
+        gtk::main();
+    }
 
     for t in threads {
         if let Ok(thr) = t {
@@ -0,0 +1,195 @@
+/// Raw audio recording to rotating WAV files
+
+
+use std::io;
+use std::fs::{self, File, OpenOptions};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use hound;
+
+use super::spectrum;
+
+
+#[derive(Debug)]
+pub (crate) enum RecordError {
+    DirError(io::Error),    // recording directory or file creation error
+    WavError(hound::Error), // WAV write error
+}
+
+impl From<hound::Error> for RecordError {
+    fn from(e: hound::Error) -> Self {
+        RecordError::WavError(e)
+    }
+}
+
+/// File name for a recording: `rec_<YYYYmmdd>_<HHMMSS>.wav`, stamped with its first sample, and
+/// numbered `_<n>` after the time if `n` isn't 0
+pub (crate) fn file_name(time: DateTime<Utc>, n: u32) -> String {
+    match n {
+        0 => format!("rec_{}.wav", time.format("%Y%m%d_%H%M%S")),
+        n => format!("rec_{}_{}.wav", time.format("%Y%m%d_%H%M%S"), n),
+    }
+}
+
+/// Create the WAV file for a first sample at `time`, numbering it when recordings started the
+/// same second (stream restarts) already exist, so none is overwritten
+fn create_file(dir: &Path, time: DateTime<Utc>) -> io::Result<(PathBuf, File)> {
+    let mut n = 0;
+    loop {
+        let path = dir.join(file_name(time, n));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// WAV file being written and the sample count at which its UTC period ends
+struct Open {
+    end_sample: u64,
+    path:       PathBuf,
+    writer:     hound::WavWriter<BufWriter<File>>,
+}
+
+/// Writes 16 bit audio into one file per UTC-aligned period
+///
+/// Samples are interleaved when there is more than one channel (I/Q).
+pub (crate) struct Recorder {
+    dir:          PathBuf,
+    period:       u32,
+    sample_rate:  u32,
+    channels:     u16,
+    anchor:       DateTime<Utc>,
+    sample_count: u64,
+    file:         Option<Open>,
+}
+
+impl Recorder {
+    pub (crate) fn new(dir: &Path, period: u32, sample_rate: u32, channels: u16) -> Self {
+        Recorder {
+            dir: dir.to_path_buf(),
+            period,
+            sample_rate,
+            channels,
+            anchor: Utc::now(),
+            sample_count: 0,
+            file: None,
+        }
+    }
+
+    /// A new stream started at `anchor`; samples that follow are counted from there
+    ///
+    /// Settings may have changed while restarting, so the stream brings its own rotation period
+    /// and format. Returns the file closed because the old stream ended.
+    pub (crate) fn start(&mut self, anchor: DateTime<Utc>, period: u32, sample_rate: u32, channels: u16) -> Result<Option<PathBuf>, RecordError> {
+        let closed = self.finish()?;
+        self.anchor = anchor;
+        self.period = period;
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        self.sample_count = 0;
+        Ok(closed)
+    }
+
+    /// Append samples, rotating files at period boundaries
+    ///
+    /// Returns the files completed while writing.
+    pub (crate) fn write(&mut self, samples: &[f32]) -> Result<Vec<PathBuf>, RecordError> {
+        let mut closed = Vec::new();
+        for &s in samples {
+            if self.file.as_ref().map_or(true, |f| self.sample_count >= f.end_sample) {
+                closed.extend(self.finish()?);
+
+                let channels = self.channels as u64;
+                let time = spectrum::sample_time(self.anchor, self.sample_count / channels, self.sample_rate);
+                let period_end = spectrum::frame_start(time, self.period) + Duration::seconds(self.period as i64);
+                // round up so the last sample before the boundary isn't left for a file of its own
+                let remaining = ((period_end - time).num_microseconds().unwrap() as u64
+                    * self.sample_rate as u64 + 999_999) / 1_000_000;
+                let end_sample = self.sample_count + remaining.max(1) * channels;
+
+                fs::create_dir_all(&self.dir).map_err(RecordError::DirError)?;
+                let (path, file) = create_file(&self.dir, time).map_err(RecordError::DirError)?;
+                let spec = hound::WavSpec {
+                    channels:        self.channels,
+                    sample_rate:     self.sample_rate,
+                    bits_per_sample: 16,
+                    sample_format:   hound::SampleFormat::Int,
+                };
+                let writer = hound::WavWriter::new(BufWriter::new(file), spec)?;
+                self.file = Some(Open { end_sample, path, writer });
+            }
+
+            let v = (s.max(-1.).min(1.) * i16::MAX as f32) as i16;
+            self.file.as_mut().unwrap().writer.write_sample(v)?;
+            self.sample_count += 1;
+        }
+        Ok(closed)
+    }
+
+    /// Close the current file, if any
+    pub (crate) fn finish(&mut self) -> Result<Option<PathBuf>, RecordError> {
+        match self.file.take() {
+            Some(f) => {
+                f.writer.finalize()?;
+                Ok(Some(f.path))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn files_split_at_the_hour_keep_every_sample() {
+        let hour = Utc.timestamp_opt(1_609_502_400, 0).unwrap();
+        // 766 µs before 12:00 at 3 kHz: 2.3 sample periods, so 3 samples belong to 11:59
+        let anchor = hour - Duration::microseconds(766);
+        let frames = 3 + 3000;
+
+        for &channels in &[1_u16, 2] {
+            let dir = tempfile::tempdir().unwrap();
+            let mut recorder = Recorder::new(dir.path(), 3600, 3000, channels);
+            recorder.start(anchor, 3600, 3000, channels).unwrap();
+
+            // I counts up, Q counts down, so swapped or shifted channels show
+            let samples: Vec<f32> = (0..frames)
+                .flat_map(|k| vec![(k % 100) as f32 / 200., -((k % 100) as f32) / 200.].into_iter().take(channels as usize))
+                .collect();
+            let mut written = Vec::new();
+            for chunk in samples.chunks(250) {
+                written.extend(recorder.write(chunk).unwrap());
+            }
+            written.extend(recorder.finish().unwrap());
+
+            // a restart within the same second goes to a file of its own
+            recorder.start(hour + Duration::milliseconds(500), 3600, 3000, channels).unwrap();
+            recorder.write(&samples[..channels as usize * 10]).unwrap();
+            written.extend(recorder.finish().unwrap());
+
+            assert_eq!(written, vec![
+                dir.path().join("rec_20210101_115959.wav"),
+                dir.path().join("rec_20210101_120000.wav"),
+                dir.path().join("rec_20210101_120000_1.wav"),
+            ]);
+            // frames in each file, and the frame each starts with
+            for (path, &(count, first)) in written.iter().zip(&[(3, 0), (frames - 3, 3), (10, 0)]) {
+                let mut reader = hound::WavReader::open(path).unwrap();
+                assert_eq!((reader.spec().channels, reader.spec().sample_rate), (channels, 3000));
+                assert_eq!(reader.len() as usize, count * channels as usize, "{:?}", path);
+
+                let read: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+                let first = first * channels as usize;
+                let expected = samples[first..first + read.len()].iter().map(|&s| (s * i16::MAX as f32) as i16);
+                assert!(read.iter().copied().eq(expected), "{:?}", path);
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+/// Waterfall rendering of processed FFT data
+
+
+use std::ops::Range;
+
+use chrono::{DateTime, Duration, Utc};
+use image::{Rgb, RgbImage};
+use colorous;
+
+use super::settings;
+use super::spectrum::{self, db, Column, FreqAxis, Frame, RowSample};
+
+
+/// Number of colors a palette is sampled into
+const PALETTE_STEPS: usize = 256;
+
+/// Color stops, bottom to top of the scale, of palettes not provided by colorous
+const GRAYSCALE: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+const ARGO:      [[u8; 3]; 5] = [[0, 0, 0], [0, 0, 128], [0, 0, 255], [255, 255, 0], [255, 255, 255]];
+
+/// Color and length in pixels of time axis ticks
+const TICK_COLOR: Rgb<u8> = Rgb([255, 255, 255]);
+const TICK_LENGTH: u32 = 6;
+
+/// Level mapped to the bottom of the gradient without auto-level, at medium brightness
+const FIXED_FLOOR_DB: f32 = -100.;
+
+/// Share of pixels below the noise floor estimate when auto-leveling
+const NOISE_PERCENTILE: f32 = 0.2;
+
+/// FFT magnitudes of one frame resampled onto image pixels
+///
+/// Row-major, highest audio frequency in the top row unless inverted for LSB. Pixels without
+/// data hold zero.
+#[derive(Debug, Clone, PartialEq)]
+pub (crate) struct Grid {
+    pub start:    DateTime<Utc>,
+    pub duration: u32,
+    pub width:    u32,
+    pub height:   u32,
+    pub data:     Vec<f32>,
+}
+
+impl Grid {
+    pub (crate) fn new(start: DateTime<Utc>, duration: u32, width: u32, height: u32) -> Self {
+        Grid {
+            start,
+            duration,
+            width,
+            height,
+            data: vec![0.; (width * height) as usize],
+        }
+    }
+
+    /// Whether `other` covers the same pixels, so the two can be combined
+    pub (crate) fn same_shape(&self, other: &Grid) -> bool {
+        self.width == other.width && self.height == other.height
+    }
+
+    /// Keep the stronger value of every pixel shared with `other`
+    pub (crate) fn max_with(&mut self, other: &Grid) {
+        for (v, o) in self.data.iter_mut().zip(&other.data) {
+            *v = v.max(*o);
+        }
+    }
+}
+
+/// Horizontal pixels touched by the time span `from` to `to` in a frame starting at `start`, if
+/// any fall inside the frame; at least one pixel for spans narrower than a pixel
+fn span_x(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    start: DateTime<Utc>,
+    duration: u32,
+    width: u32,
+) -> Option<Range<u32>> {
+    let frame_ms = duration as i64 * 1000;
+    let (a, b) = ((from - start).num_milliseconds(), (to - start).num_milliseconds());
+    if a >= frame_ms || b < 0 || (b == 0 && a < 0) {
+        return None;
+    }
+    let x0 = a.max(0) * width as i64 / frame_ms;
+    let x1 = (b.min(frame_ms) * width as i64 + frame_ms - 1) / frame_ms;
+    Some(x0 as u32..(x1.max(x0 + 1) as u32).min(width))
+}
+
+/// Horizontal pixel and UTC time of each time tick in a frame `width` pixels wide
+pub (crate) fn time_ticks(start: DateTime<Utc>, duration: u32, width: u32) -> Vec<(u32, DateTime<Utc>)> {
+    let interval = spectrum::tick_interval(duration);
+
+    // ticks fall on wall-clock multiples of the interval
+    let offset = (interval as i64 - start.timestamp().rem_euclid(interval as i64)) % interval as i64;
+
+    (offset as u32..duration).step_by(interval as usize)
+        .map(|t| ((t as u64 * width as u64 / duration as u64) as u32, start + Duration::seconds(t as i64)))
+        .collect()
+}
+
+/// Draw UTC time ticks along the top and bottom edges
+fn draw_time_axis(img: &mut RgbImage, start: DateTime<Utc>, duration: u32) {
+    let (w, h) = img.dimensions();
+    let len = TICK_LENGTH.min(h);
+
+    for (x, _) in time_ticks(start, duration, w) {
+        for y in (0..len).chain(h - len..h) {
+            img.put_pixel(x, y, TICK_COLOR);
+        }
+    }
+}
+
+/// Resample the columns of a UTC-aligned frame onto a pixel grid
+///
+/// Columns are placed by timestamp, so the frame fills left to right as it is captured and gaps
+/// in capture stay empty. Rows are mapped per column, so a frame may mix FFT sizes and rates.
+/// Columns wider than a pixel are stretched, columns sharing a pixel keep the strongest value.
+/// `inverted` puts the lowest audio frequency at the top.
+pub (crate) fn grid(frame: &Frame, freq_range: &[i32], dimensions: &[u32], inverted: bool) -> Grid {
+    let (img_x, img_y) = (dimensions[0], dimensions[1]);
+    let mut grid = Grid::new(frame.start, frame.duration, img_x, img_y);
+
+    let placed: Vec<(Range<u32>, &Column)> = frame.columns.iter()
+        .filter(|c| !c.bins.is_empty())
+        .filter_map(|c| {
+            let half = c.interval / 2;
+            span_x(c.time - half, c.time + half, frame.start, frame.duration, img_x).map(|x| (x, &**c))
+        })
+        .collect();
+
+    // row mapping only changes when the FFT was rebuilt
+    let mut layout: Option<(FreqAxis, Vec<RowSample>)> = None;
+    for (xs, col) in &placed {
+        let axis = FreqAxis::of(col);
+        if layout.as_ref().map_or(true, |(a, _)| *a != axis) {
+            let mut rows = axis.rows(freq_range, img_y);
+            if inverted {
+                rows.reverse();
+            }
+            layout = Some((axis, rows));
+        }
+        let rows = &layout.as_ref().unwrap().1;
+        for (y, row) in rows.iter().enumerate() {
+            let v = col.sample(row);
+            for x in xs.clone() {
+                let px = &mut grid.data[y * img_x as usize + x as usize];
+                *px = px.max(v);
+            }
+        }
+    }
+
+    grid
+}
+
+/// Color at `t` (0 to 1) of a gradient through evenly spaced `stops`
+fn stop_color(stops: &[[u8; 3]], t: f32) -> Rgb<u8> {
+    let pos = t * (stops.len() - 1) as f32;
+    let i = (pos as usize).min(stops.len() - 2);
+    let f = pos - i as f32;
+    let (a, b) = (stops[i], stops[i + 1]);
+    let mix = |c: usize| (a[c] as f32 + (b[c] as f32 - a[c] as f32) * f).round() as u8;
+    Rgb([mix(0), mix(1), mix(2)])
+}
+
+/// Palette of `set` sampled into `PALETTE_STEPS` colors, bottom to top of the scale
+///
+/// A custom palette without colors (its file failed to load) falls back to viridis.
+fn palette(set: &settings::Image) -> Vec<Rgb<u8>> {
+    let gradient = match set.palette {
+        settings::Palette::Viridis   => Some(colorous::VIRIDIS),
+        settings::Palette::Inferno   => Some(colorous::INFERNO),
+        settings::Palette::Magma     => Some(colorous::MAGMA),
+        settings::Palette::Plasma    => Some(colorous::PLASMA),
+        settings::Palette::Cividis   => Some(colorous::CIVIDIS),
+        settings::Palette::Turbo     => Some(colorous::TURBO),
+        settings::Palette::Custom if set.gradient.len() < 2 => Some(colorous::VIRIDIS),
+        _ => None,
+    };
+    let stops: &[[u8; 3]] = match set.palette {
+        settings::Palette::Grayscale => &GRAYSCALE,
+        settings::Palette::Argo      => &ARGO,
+        _                            => &set.gradient,
+    };
+
+    (0..PALETTE_STEPS).map(|i| {
+        let t = i as f32 / (PALETTE_STEPS - 1) as f32;
+        match gradient {
+            Some(g) => {
+                let c = g.eval_continuous(t as f64);
+                Rgb([c.r, c.g, c.b])
+            },
+            None => stop_color(stops, t),
+        }
+    }).collect()
+}
+
+/// Noise floor estimate in dB: a low percentile of all pixels holding data
+fn noise_floor(grid: &Grid) -> Option<f32> {
+    let mut levels: Vec<f32> = grid.data.iter().filter(|&&v| v > 0.).map(|&v| db(v)).collect();
+    if levels.is_empty() {
+        return None;
+    }
+    let n = ((levels.len() - 1) as f32 * NOISE_PERCENTILE) as usize;
+    let (_, floor, _) = levels.select_nth_unstable_by(n, |a, b| a.partial_cmp(b).unwrap());
+    Some(*floor)
+}
+
+/// dB levels mapped to the bottom and top of the gradient
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) struct Levels {
+    pub floor: f32,
+    pub range: f32,
+}
+
+impl Levels {
+    /// Contrast narrows the displayed range (80 dB to 10 dB); brightness lowers the floor by up
+    /// to 20 dB either way, from a fixed level or from the noise floor of `grid` with auto-level
+    pub (crate) fn new(grid: &Grid, set: &settings::Image) -> Self {
+        let range = 10. + (100 - set.contrast.min(100)) as f32 * 0.7;
+        let offset = (set.brightness.min(100) as f32 - 50.) * 0.4;
+        let base = match (set.auto_level, noise_floor(grid)) {
+            // keep the noise a little above black so band conditions stay visible
+            (true, Some(noise)) => noise - 0.1 * range,
+            _ => FIXED_FLOOR_DB,
+        };
+        Levels {
+            floor: base - offset,
+            range,
+        }
+    }
+
+    /// Position of `magnitude` on the gradient, 0 to 1
+    pub (crate) fn scale(&self, magnitude: f32) -> f32 {
+        ((db(magnitude) - self.floor) / self.range).max(0.).min(1.)
+    }
+}
+
+/// Color a grid into an image with time ticks; magnitudes are shown in dB with levels from `set`
+pub (crate) fn image(grid: &Grid, set: &settings::Image) -> RgbImage {
+    let mut img = RgbImage::new(grid.width, grid.height);
+    let levels = Levels::new(grid, set);
+    let colors = palette(set);
+
+    for (i, &v) in grid.data.iter().enumerate() {
+        let c = colors[(levels.scale(v) * (PALETTE_STEPS - 1) as f32).round() as usize];
+        let (x, y) = (i as u32 % grid.width, i as u32 / grid.width);
+        img.put_pixel(x, y, c);
+    }
+
+    draw_time_axis(&mut img, grid.start, grid.duration);
+    img
+}
+
+/// Render a UTC-aligned frame into a waterfall image with the dimensions and levels of `set`
+pub (crate) fn waterfall(frame: &Frame, freq_range: &[i32], inverted: bool, set: &settings::Image) -> RgbImage {
+    image(&grid(frame, freq_range, &set.dimensions, inverted), set)
+}
@@ -0,0 +1,148 @@
+/// Re-rendering of archived or recorded spectra for an arbitrary UTC window
+///
+/// Inputs are spectral archives (`.qsa`) or WAV/FLAC recordings; recordings run through the FFT
+/// chain of the current settings. Nothing touches the audio hardware.
+
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+
+use super::analyzer::Analyzer;
+use super::annotate::Labels;
+use super::archive::{self, ArchiveError};
+use super::export::{self, ExportError};
+use super::render::{self, Grid};
+use super::settings::{self, RenderJob};
+use super::source::{self, SourceError};
+use super::spectrum::{Column, Frame};
+
+
+/// Columns resampled onto the image at a time, bounding memory on long windows
+const BATCH_COLUMNS: usize = 64;
+
+#[derive(Debug)]
+pub (crate) enum ReplayError {
+    ArchiveError(ArchiveError),  // archive read error
+    SourceError(SourceError),    // recording read error
+    ExportError(ExportError),    // image write error
+    TimeError(String),           // empty or inverted time window
+}
+
+impl From<ArchiveError> for ReplayError {
+    fn from(e: ArchiveError) -> Self {
+        ReplayError::ArchiveError(e)
+    }
+}
+
+impl From<SourceError> for ReplayError {
+    fn from(e: SourceError) -> Self {
+        ReplayError::SourceError(e)
+    }
+}
+
+impl From<ExportError> for ReplayError {
+    fn from(e: ExportError) -> Self {
+        ReplayError::ExportError(e)
+    }
+}
+
+fn is_archive(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).map_or(false, |e| e.eq_ignore_ascii_case("qsa"))
+}
+
+/// Hand every column of `path` to `f`, analyzing recordings with the FFT settings of `set`
+fn read_columns<F: FnMut(Column)>(path: &Path, set: &settings::Settings, mut f: F) -> Result<(), ReplayError> {
+    if is_archive(path) {
+        archive::read_file(path, f)?;
+        return Ok(());
+    }
+
+    // recordings are processed at their native rate; stereo is I/Q only when asked for
+    let info = source::probe(path)?;
+    let mut set = set.clone();
+    set.audio.rate = info.sample_rate;
+    set.audio.iq = set.audio.iq && info.channels == 2;
+    let mut analyzer = Analyzer::new(&set, info.start_time(path));
+    source::read_file(path, |d| {
+        if set.audio.iq {
+            analyzer.process(d, &mut f);
+        } else {
+            analyzer.process(&source::downmix(d, info.channels), &mut f);
+        }
+    })?;
+    Ok(())
+}
+
+/// First and last column time over all `inputs`
+fn data_span(inputs: &[PathBuf]) -> Result<Option<(DateTime<Utc>, DateTime<Utc>)>, ReplayError> {
+    let mut span: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+    let mut extend = |t: DateTime<Utc>| {
+        span = Some(match span {
+            Some((a, b)) => (a.min(t), b.max(t)),
+            None         => (t, t),
+        });
+    };
+    for path in inputs {
+        if is_archive(path) {
+            archive::read_file(path, |c| extend(c.time))?;
+        } else {
+            // recordings have a known length, no need to decode them twice
+            let info = source::probe(path)?;
+            let start = info.start_time(path);
+            extend(start);
+            extend(start + Duration::milliseconds((info.frames * 1000 / info.sample_rate as u64) as i64));
+        }
+    }
+    Ok(span)
+}
+
+/// Render the columns of `job.inputs` between `job.from` and `job.to` into one image
+///
+/// The window defaults to the span of the data. Frequency range, dimensions and levels come from
+/// `set`; the image is saved to the export directory, stamped with the window start.
+pub (crate) fn render(job: &RenderJob, set: &settings::Settings, logger: &slog::Logger) -> Result<PathBuf, ReplayError> {
+    let (from, to) = match (job.from, job.to) {
+        (Some(from), Some(to)) => (from, to),
+        (from, to) => match data_span(&job.inputs)? {
+            Some((a, b)) => (from.unwrap_or(a), to.unwrap_or(b)),
+            None => return Err(ReplayError::TimeError(String::from("No data in inputs"))),
+        },
+    };
+    if to <= from {
+        return Err(ReplayError::TimeError(format!("Window ends before it starts: {} to {}", from, to)));
+    }
+    // whole seconds, rounded up so the last column fits
+    let duration = ((to - from).num_milliseconds() + 999) / 1000;
+    info!(logger, "Rendering {} to {} ({} s)", from, to, duration);
+
+    let freq_range = &set.audio.freq_range;
+    let dimensions = &set.image.dimensions;
+    let inverted = set.radio.inverted();
+    let mut grid = Grid::new(from, duration as u32, dimensions[0], dimensions[1]);
+    let mut frame = Frame {
+        start:    from,
+        duration: duration as u32,
+        columns:  Vec::with_capacity(BATCH_COLUMNS),
+    };
+
+    for path in &job.inputs {
+        info!(logger, "Reading {:?}", path);
+        read_columns(path, set, |column| {
+            if column.time < from || column.time > to {
+                return;
+            }
+            frame.columns.push(Arc::new(column));
+            if frame.columns.len() == BATCH_COLUMNS {
+                grid.max_with(&render::grid(&frame, freq_range, dimensions, inverted));
+                frame.columns.clear();
+            }
+        })?;
+    }
+    grid.max_with(&render::grid(&frame, freq_range, dimensions, inverted));
+
+    let img = export::export_image(&grid, &set.image, &set.export, &Labels::new(set));
+    let path = export::save(&img, &set.export.path, &set.names.render, from)?;
+    Ok(path)
+}
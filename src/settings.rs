@@ -3,14 +3,17 @@
 
 use std::io;
 use std::io::prelude::*;
-use std::fs::OpenOptions;
-use std::path::PathBuf;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
 
 use clap;
 use clap::clap_app;
 
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+
 use shellexpand as se;
-use config::{Config, ConfigError, File as cFile};
+use config::{Config, ConfigBuilder, ConfigError, File as cFile};
+use config::builder::DefaultState;
 
 use toml;
 use serde::{Serialize, Deserialize};
@@ -18,9 +21,14 @@ use serde::{Serialize, Deserialize};
 use cpal;
 use cpal::traits::*;
 
+use slog;
+
 use super::windows;
 
 
+/// Config written on first run
+const DEFAULT_CONFIG: &'static str = include_str!("../assets/default.toml");
+
 pub (crate) fn clap_args() -> clap::ArgMatches<'static> {
     let path_exists = |path: String| {
         if se::full(&path).is_ok() {
@@ -29,15 +37,21 @@ pub (crate) fn clap_args() -> clap::ArgMatches<'static> {
             Err(String::from("File does not exist"))
         }
     };
+    let file_exists = |path: String| {
+        match se::full(&path) {
+            Ok(p) if Path::new(&*p).is_file() => Ok(()),
+            _ => Err(String::from("File does not exist")),
+        }
+    };
     let f_range = |range: String| {
-        if let Ok(val) = range.parse::<u16>() {
-            if val <= 3000 && val >= 50 {
+        if let Ok(val) = range.parse::<i32>() {
+            if val <= 96000 && val >= -96000 {
                 Ok(())
             } else {
-                Err(String::from("Maximum range: 50-3000"))
+                Err(String::from("Maximum range: -96000-96000 (audio: 50-3000, I/Q: +/- half the sample rate)"))
             }
         } else {
-            Err(String::from("Positive integer inputs only"))
+            Err(String::from("Integer inputs only"))
         }
     };
     let d_range = |range: String| {
@@ -51,6 +65,17 @@ pub (crate) fn clap_args() -> clap::ArgMatches<'static> {
             Err(String::from("Integer values only"))
         }
     };
+    let t_range = |val: String| {
+        if let Ok(v) = val.parse::<u32>() {
+            if v >= 60 && v <= 21600 {
+                Ok(())
+            } else {
+                Err(String::from("Range: 60-21600 seconds"))
+            }
+        } else {
+            Err(String::from("Integer values only"))
+        }
+    };
     let c_b_range = |val: String| {
         if let Ok(v) = val.parse::<u8>() {
             if v <= 100 {
@@ -62,6 +87,56 @@ pub (crate) fn clap_args() -> clap::ArgMatches<'static> {
             Err(String::from("Integer range only"))
         }
     };
+    let res_range = |val: String| {
+        if let Ok(v) = val.parse::<f32>() {
+            if v == 0. || (v >= 0.05 && v <= 100.) {
+                Ok(())
+            } else {
+                Err(String::from("Range: 0.05-100 Hz (0: derive from dot length)"))
+            }
+        } else {
+            Err(String::from("Numeric values only"))
+        }
+    };
+    let o_range = |val: String| {
+        if let Ok(v) = val.parse::<u8>() {
+            if v <= 95 {
+                Ok(())
+            } else {
+                Err(String::from("Range: 0-95"))
+            }
+        } else {
+            Err(String::from("Integer values only"))
+        }
+    };
+    let p_range = |val: String| {
+        if let Ok(v) = val.parse::<u8>() {
+            if v >= 1 && v <= 99 {
+                Ok(())
+            } else {
+                Err(String::from("Range: 1-99"))
+            }
+        } else {
+            Err(String::from("Integer values only"))
+        }
+    };
+    let dial_range = |val: String| {
+        if let Ok(v) = val.parse::<u64>() {
+            if v <= 30_000_000_000 {
+                Ok(())
+            } else {
+                Err(String::from("Range: 0-30 GHz (0: no dial frequency)"))
+            }
+        } else {
+            Err(String::from("Integer values only"))
+        }
+    };
+    let utc_time = |val: String| {
+        match parse_utc(&val) {
+            Some(_) => Ok(()),
+            None => Err(String::from("UTC time as YYYY-MM-DD HH:MM[:SS] or RFC 3339")),
+        }
+    };
     let aud_exists = |device: String| {
         if cpal::default_host().devices().unwrap().any(|x| x.name().unwrap() == device) {
             Ok(())
@@ -73,25 +148,125 @@ pub (crate) fn clap_args() -> clap::ArgMatches<'static> {
     clap_app!(QRuSSt =>
         (about: "A QRSS processor using audio input from a sound card or SDR demodulator")
         (@arg verbose:         -v --verbose         ...                                                             "stdout verbosity (can be passed up to twice)"                    )
+        (@arg headless:        -H --headless                     display_order(1)                                   "Run without GUI; quit on SIGINT/SIGTERM"                         )
         (@arg save_prefs:      -s --("save-prefs")               display_order(1)                                   "Write given arguments to config file"                            )
         (@arg config:          -c --config          [FILE]       display_order(1) number_of_values(1) {path_exists} "Path to config file (default: ~/.config/QRuSSt/config.toml)"     )
 
         (@arg window:          -w --window                       display_order(4)                                   "Use window dimensions for image export"                          )
         (@arg dimensions:      -D --dimensions      [X] [Y]      display_order(3) number_of_values(2) {d_range}     "Pixel dimensions for export (see --window)"                      )
-        (@arg brightness:      -B --brightness      [NUM]        display_order(3) number_of_values(1)               "Image brightness (0-100)"                                        )
+        (@arg brightness:      -B --brightness      [NUM]        display_order(3) number_of_values(1) {c_b_range}   "Image brightness (0-100)"                                        )
         (@arg contrast:        -C --contrast        [NUM]        display_order(3) number_of_values(1) {c_b_range}   "Image contrast (0-100)"                                          )
+        (@arg palette:         -P --palette         [NAME]       display_order(3) number_of_values(1)
+             possible_values(&["viridis", "inferno", "magma", "plasma", "cividis", "turbo", "grayscale", "argo", "custom"])
+             "Waterfall color palette")
+        (@arg palette_file:    --("palette-file")   [FILE]       display_order(3) number_of_values(1) {file_exists} "Custom palette gradient (TOML, implies --palette custom)"        )
+        (@arg levels:          -l --levels          [MODE]       display_order(3) number_of_values(1)
+             possible_values(&["auto", "fixed"])
+             "Track the noise floor or use fixed levels for brightness/contrast")
 
         (@arg export_images:   -i --images                       display_order(3)                                   "Enable image export"                                             )
         (@arg export_path:     -E --("export-path") [DIR]        display_order(4) number_of_values(1) {path_exists} "Image export directory (default: ~/.local/share/QRuSSt/export/)" )
+        (@arg plain:           --plain                           display_order(4)                                   "Export bare waterfalls without scales and header"                )
+        (@arg callsign:        --callsign           [CALL]       display_order(4) number_of_values(1)               "Station callsign shown on exports"                               )
+        (@arg locator:         --locator            [GRID]       display_order(4) number_of_values(1)               "Station locator shown on exports"                                )
+        (@arg dial:            --dial               [HZ]         display_order(2) number_of_values(1) {dial_range}  "Receiver dial frequency in Hz, for RF scales on exports"         )
+        (@arg sideband:        --sideband           [SB]         display_order(2) number_of_values(1)
+             possible_values(&["usb", "lsb"])
+             "Receiver sideband (LSB draws images upside down)")
+        (@arg band:            --band               [NAME]       display_order(2) number_of_values(1)
+             possible_values(&["2200m", "137k", "630m", "475k", "80m", "40m", "30m", "20m", "15m", "10m"])
+             "Band preset: sets dial frequency for the sideband (default USB) and the frequency range to the QRSS window")
+
+        (@arg iq:              -q --iq                           display_order(2)                                   "Treat stereo input as I/Q baseband from an SDR"                  )
+        (@arg input_file:      -f --("input-file")  [FILE]       display_order(2) number_of_values(1) {file_exists} "Process a WAV or FLAC recording instead of a live device"         )
+        (@arg record:          -R --record                       display_order(4)                                   "Record captured audio to WAV files"                              )
+        (@arg record_path:     --("record-path")    [DIR]        display_order(4) number_of_values(1) {path_exists} "Recording directory (default: ~/.local/share/QRuSSt/recordings/)")
+        (@arg record_rotate:   --("record-rotate")  [PERIOD]     display_order(4) number_of_values(1)
+             possible_values(&["frame", "hour"])
+             "Start a new recording every frame or every hour")
+
+        (@arg archive:         -A --archive                      display_order(4)                                   "Archive FFT columns to hourly binary files"                      )
+        (@arg archive_path:    --("archive-path")   [DIR]        display_order(4) number_of_values(1) {path_exists} "Archive directory (default: ~/.local/share/QRuSSt/archive/)"     )
+        (@arg archive_format:  --("archive-format") [FORMAT]     display_order(4) number_of_values(1)
+             possible_values(&["f32", "u16", "u8"])
+             "Store f32 magnitudes, or dB quantized to u16/u8")
 
         (@arg device:          -d --device          [NAME]       display_order(2) number_of_values(1) {aud_exists}  "Audio device to use (use device name from `arecord -L`)"         )
-        (@arg frequency_range: -F --("f-range")     [LOW] [HIGH] display_order(2) number_of_values(2) {f_range}     "Audio frequency range to process/display (maximum range: 0-3000)")
+        (@arg frequency_range: -F --("f-range")     [LOW] [HIGH] display_order(2) number_of_values(2) allow_hyphen_values(true) {f_range}
+             "Frequency range to process/display (audio: 50-3000, I/Q: negative allowed)")
         (@arg rate:            -r --rate            [SAMPLES]    display_order(2) number_of_values(1)
              possible_values(&["16000", "32000", "44100", "48000", "96000", "192000"])
              "Audio device sample rate")
+        (@arg format:          -S --("sample-format") [FORMAT]   display_order(2) number_of_values(1)
+             possible_values(&["i16", "u16", "f32"])
+             "Audio device sample format")
+
+        (@arg fft_window:      -W --("fft-window")  [TYPE]       display_order(2) number_of_values(1)
+             possible_values(&["rectangle", "cosine", "triangle", "hamming", "hann", "blackman", "nuttall", "flat"])
+             "FFT window function")
+        (@arg resolution:      -Z --resolution      [HZ]         display_order(2) number_of_values(1) {res_range}   "FFT resolution in Hz per bin (0: derive from dot length)"        )
+        (@arg overlap:         -O --overlap         [PERCENT]    display_order(2) number_of_values(1) {o_range}     "Overlap of consecutive FFT windows (0-95%)"                      )
+        (@arg background:      -b --("subtract-background")      display_order(2)                                   "Flatten the passband by subtracting a per-bin background"        )
+        (@arg blanker:         -I --("blank-impulses")           display_order(2)                                   "Attenuate columns whose energy spikes (static crashes)"          )
+        (@arg notch:           -N --("notch-carriers")           display_order(2)                                   "Remove persistent narrow carriers (birdies)"                     )
+        (@arg bg_percentile:   --("background-percentile") [PERCENT] display_order(2) number_of_values(1) {p_range} "Percentile of each bin's level taken as background (1-99)"      )
+
+        (@arg frame_duration:  -T --("frame-duration") [SECONDS] display_order(2) number_of_values(1) {t_range}  "Time span of one grab (60-21600 seconds)"                        )
+        (@arg dot_length:      -L --("dot-length")  [SECONDS]    display_order(2) number_of_values(1)
+             possible_values(&["1", "3", "6", "10", "30", "60", "120"])
+             "QRSS dot length in seconds")
+
+        (@subcommand render =>
+            (about: "Render archived spectra or recordings into an image, without audio hardware")
+            (@arg inputs:          +required +multiple                                   {file_exists} "Spectral archives (.qsa), WAV or FLAC recordings"                )
+            (@arg from:            --from               [TIME]       number_of_values(1) {utc_time}    "Window start, UTC (default: first column)"                       )
+            (@arg to:              --to                 [TIME]       number_of_values(1) {utc_time}    "Window end, UTC (default: last column)"                          )
+            (@arg frequency_range: -F --("f-range")     [LOW] [HIGH] number_of_values(2) allow_hyphen_values(true) {f_range}
+                 "Frequency range to display (I/Q: negative allowed)")
+            (@arg dimensions:      -D --dimensions      [X] [Y]      number_of_values(2) {d_range}     "Pixel dimensions of the image"                                   )
+            (@arg brightness:      -B --brightness      [NUM]        number_of_values(1) {c_b_range}   "Image brightness (0-100)"                                        )
+            (@arg contrast:        -C --contrast        [NUM]        number_of_values(1) {c_b_range}   "Image contrast (0-100)"                                          )
+            (@arg palette:         -P --palette         [NAME]       number_of_values(1)
+                 possible_values(&["viridis", "inferno", "magma", "plasma", "cividis", "turbo", "grayscale", "argo", "custom"])
+                 "Waterfall color palette")
+            (@arg palette_file:    --("palette-file")   [FILE]       number_of_values(1) {file_exists} "Custom palette gradient (TOML, implies --palette custom)"        )
+            (@arg levels:          -l --levels          [MODE]       number_of_values(1)
+                 possible_values(&["auto", "fixed"])
+                 "Track the noise floor or use fixed levels for brightness/contrast")
+            (@arg export_path:     -E --("export-path") [DIR]        number_of_values(1) {path_exists} "Image directory (default: export path)"                          )
+        )
     ).get_matches()
 }
 
+/// UTC time given as RFC 3339, or as `YYYY-MM-DD HH:MM[:SS]` taken to be UTC
+fn parse_utc(s: &str) -> Option<DateTime<Utc>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Some(t.with_timezone(&Utc));
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"].iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .map(|t| Utc.from_utc_datetime(&t))
+}
+
+/// Files and UTC window to re-render, given with the `render` subcommand
+#[derive(Debug, Clone, PartialEq)]
+pub (crate) struct RenderJob {
+    pub inputs: Vec<PathBuf>,
+    pub from:   Option<DateTime<Utc>>,
+    pub to:     Option<DateTime<Utc>>,
+}
+
+/// The render job asked for on the command line, if any
+pub (crate) fn render_job(cli: &clap::ArgMatches) -> Option<RenderJob> {
+    let r = cli.subcommand_matches("render")?;
+    // Validators already checked expansion and parsing. Safe to unwrap.
+    Some(RenderJob {
+        inputs: r.values_of("inputs")?.map(|f| (*se::full(f).unwrap()).into()).collect(),
+        from:   r.value_of("from").map(|t| parse_utc(t).unwrap()),
+        to:     r.value_of("to").map(|t| parse_utc(t).unwrap()),
+    })
+}
+
 #[derive(Debug)]
 pub (crate) enum SettingsError {
     ConfigError(ConfigError),    // config::ConfigError
@@ -99,6 +274,7 @@ pub (crate) enum SettingsError {
     WriteError(io::Error),       // file write error
     DeserError(toml::de::Error), // data deserialize error
     SerError(toml::ser::Error),  // data serialize error
+    GradientError(String),       // invalid custom palette color
 }
 
 impl From<ConfigError> for SettingsError {
@@ -119,19 +295,45 @@ pub (crate) enum FftWindowType {
     Flat,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+impl FftWindowType {
+    pub (crate) const ALL: [FftWindowType; 8] = [
+        FftWindowType::Rectangle,
+        FftWindowType::Cosine,
+        FftWindowType::Triangle,
+        FftWindowType::Hamming,
+        FftWindowType::Hann,
+        FftWindowType::Blackman,
+        FftWindowType::Nuttall,
+        FftWindowType::Flat,
+    ];
+
+    /// Lower case name used on the command line and in the GUI
+    pub (crate) fn name(&self) -> &'static str {
+        match self {
+            FftWindowType::Rectangle => "rectangle",
+            FftWindowType::Cosine    => "cosine",
+            FftWindowType::Triangle  => "triangle",
+            FftWindowType::Hamming   => "hamming",
+            FftWindowType::Hann      => "hann",
+            FftWindowType::Blackman  => "blackman",
+            FftWindowType::Nuttall   => "nuttall",
+            FftWindowType::Flat      => "flat",
+        }
+    }
+
+    pub (crate) fn from_name(name: &str) -> Option<Self> {
+        FftWindowType::ALL.iter().find(|w| w.name() == name).copied()
+    }
+}
+
+/// Window function of a given length, generated whenever the FFT is planned
+#[derive(Debug, PartialEq, Clone)]
 pub (crate) struct FftWindow {
     pub window_type: FftWindowType,
     pub length: usize,
     pub window_func: Vec<f32>,
 }
 
-impl Default for FftWindow {
-    fn default() -> Self {
-        FftWindow::new(32768, &FftWindowType::Hann)
-    }
-}
-
 impl FftWindow {
     pub (crate) fn new(length: usize, window_type: &FftWindowType) -> Self {
         FftWindow {
@@ -151,11 +353,104 @@ impl FftWindow {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub (crate) enum Palette {
+    Viridis,
+    Inferno,
+    Magma,
+    Plasma,
+    Cividis,
+    Turbo,
+    Grayscale,
+    Argo,
+    Custom,
+}
+
+impl Palette {
+    pub (crate) const ALL: [Palette; 9] = [
+        Palette::Viridis,
+        Palette::Inferno,
+        Palette::Magma,
+        Palette::Plasma,
+        Palette::Cividis,
+        Palette::Turbo,
+        Palette::Grayscale,
+        Palette::Argo,
+        Palette::Custom,
+    ];
+
+    /// Lower case name used on the command line and in the GUI
+    pub (crate) fn name(&self) -> &'static str {
+        match self {
+            Palette::Viridis   => "viridis",
+            Palette::Inferno   => "inferno",
+            Palette::Magma     => "magma",
+            Palette::Plasma    => "plasma",
+            Palette::Cividis   => "cividis",
+            Palette::Turbo     => "turbo",
+            Palette::Grayscale => "grayscale",
+            Palette::Argo      => "argo",
+            Palette::Custom    => "custom",
+        }
+    }
+
+    pub (crate) fn from_name(name: &str) -> Option<Self> {
+        Palette::ALL.iter().find(|p| p.name() == name).copied()
+    }
+}
+
+/// Custom palette file: colors from the bottom to the top of the scale, evenly spaced
+///
+/// ```toml
+/// colors = ["#000000", "#0000a0", "#ffff00", "#ffffff"]
+/// ```
+#[derive(Debug, Deserialize)]
+struct GradientFile {
+    colors: Vec<String>,
+}
+
+/// Read the colors of a custom palette file
+pub (crate) fn load_gradient(path: &Path) -> Result<Vec<[u8; 3]>, SettingsError> {
+    let text = fs::read_to_string(path).map_err(SettingsError::ReadError)?;
+    let file: GradientFile = toml::from_str(&text).map_err(SettingsError::DeserError)?;
+    if file.colors.len() < 2 {
+        return Err(SettingsError::GradientError(String::from("At least two colors needed")));
+    }
+    file.colors.iter().map(|c| {
+        let hex = c.trim_start_matches('#');
+        match u32::from_str_radix(hex, 16) {
+            Ok(v) if hex.len() == 6 => Ok([(v >> 16) as u8, (v >> 8) as u8, v as u8]),
+            _ => Err(SettingsError::GradientError(format!("Not a #rrggbb color: {:?}", c))),
+        }
+    }).collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub (crate) enum AudioFormat {
+    i16,
+    u16,
+    f32,
+}
+
+impl From<AudioFormat> for cpal::SampleFormat {
+    fn from(f: AudioFormat) -> Self {
+        match f {
+            AudioFormat::i16 => cpal::SampleFormat::I16,
+            AudioFormat::u16 => cpal::SampleFormat::U16,
+            AudioFormat::f32 => cpal::SampleFormat::F32,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub (crate) struct Audio {
     pub device:     String,
     pub rate:       u32,
-    pub freq_range: Vec<u32>,
+    pub format:     AudioFormat,
+    pub freq_range: Vec<i32>,
+    pub iq:         bool,
+    #[serde(skip)]
+    pub input_file: Option<PathBuf>, // recording to process instead of `device`
 }
 
 impl Default for Audio {
@@ -163,7 +458,181 @@ impl Default for Audio {
         Audio {
             device:    "default".to_string(),
             rate:       48000,
+            format:     AudioFormat::i16,
             freq_range: vec![100, 2800],
+            iq:         false,
+            input_file: None,
+        }
+    }
+}
+
+impl Audio {
+    /// Lowest frequency that can be shown: audio has no negative frequencies, I/Q baseband does
+    pub (crate) fn min_freq(&self) -> i32 {
+        if self.iq { -96000 } else { 0 }
+    }
+
+    /// Raise `freq_range` to what the input can show, the default range if none of it is left;
+    /// returns whether it changed
+    pub (crate) fn clamp_freq_range(&mut self) -> bool {
+        let min = self.min_freq();
+        if self.freq_range[0] >= min {
+            return false;
+        }
+        self.freq_range = if self.freq_range[1] > min {
+            vec![min, self.freq_range[1]]
+        } else {
+            Audio::default().freq_range
+        };
+        true
+    }
+}
+
+/// Receiver sideband: audio frequencies add to the dial (USB) or subtract from it (LSB)
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub (crate) enum Sideband {
+    #[serde(rename = "USB")]
+    Usb,
+    #[serde(rename = "LSB")]
+    Lsb,
+}
+
+/// Usual QRSS dial frequency and window of a band
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) struct BandPreset {
+    pub name:      &'static str,
+    pub dial_freq: u64,        // USB dial in Hz
+    pub window:    [u64; 2],   // QRSS window in RF Hz
+}
+
+impl BandPreset {
+    /// Dial frequency for `sideband`; the LSB dial mirrors the USB one about the window center
+    pub (crate) fn dial(&self, sideband: Sideband) -> u64 {
+        match sideband {
+            Sideband::Usb => self.dial_freq,
+            Sideband::Lsb => self.window[0] + self.window[1] - self.dial_freq,
+        }
+    }
+
+    /// Audio frequency range showing the window, with some room on either side
+    ///
+    /// The same on either sideband when tuned to `dial`.
+    pub (crate) fn freq_range(&self) -> Vec<i32> {
+        let margin = (self.window[1] - self.window[0]) / 4;
+        vec![
+            (self.window[0] - margin - self.dial_freq) as i32,
+            (self.window[1] + margin - self.dial_freq) as i32,
+        ]
+    }
+}
+
+/// Band presets, by band name and frequency
+pub (crate) const BANDS: [BandPreset; 8] = [
+    BandPreset { name: "2200m", dial_freq:    136_000, window: [   137_600,    137_800] }, // 137 kHz
+    BandPreset { name: "630m",  dial_freq:    474_200, window: [   475_700,    475_900] }, // 475 kHz
+    BandPreset { name: "80m",   dial_freq:  3_568_600, window: [ 3_569_900,  3_570_100] },
+    BandPreset { name: "40m",   dial_freq:  7_038_600, window: [ 7_039_800,  7_040_000] },
+    BandPreset { name: "30m",   dial_freq: 10_138_700, window: [10_140_000, 10_140_100] },
+    BandPreset { name: "20m",   dial_freq: 14_095_600, window: [14_096_800, 14_097_000] },
+    BandPreset { name: "15m",   dial_freq: 21_095_600, window: [21_096_800, 21_097_000] },
+    BandPreset { name: "10m",   dial_freq: 28_124_600, window: [28_125_600, 28_126_000] },
+];
+
+/// Band preset named `name`; also accepts the frequency names `137k` and `475k`
+pub (crate) fn band_preset(name: &str) -> Option<BandPreset> {
+    let name = match name.to_lowercase().as_str() {
+        "137k" => String::from("2200m"),
+        "475k" => String::from("630m"),
+        n      => n.to_string(),
+    };
+    BANDS.iter().find(|b| b.name == name).copied()
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub (crate) struct Radio {
+    pub dial_freq: u64,      // receiver dial in Hz, 0 if unknown
+    pub sideband:  Sideband,
+    pub band:      String,   // band name shown on exports; presets apply from `--band` or the GUI only
+}
+
+impl Radio {
+    /// RF frequency in Hz of `audio` Hz, with the dial frequency known
+    pub (crate) fn rf(&self, audio: i64) -> Option<i64> {
+        match (self.dial_freq, self.sideband) {
+            (0, _)             => None,
+            (d, Sideband::Usb) => Some(d as i64 + audio),
+            (d, Sideband::Lsb) => Some(d as i64 - audio),
+        }
+    }
+
+    /// Whether high audio frequencies are low RF frequencies, so images are drawn upside down
+    /// to keep RF rising to the top
+    pub (crate) fn inverted(&self) -> bool {
+        self.sideband == Sideband::Lsb
+    }
+}
+
+impl Default for Radio {
+    fn default() -> Self {
+        Radio {
+            dial_freq: 0,
+            sideband:  Sideband::Usb,
+            band:      String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub (crate) struct Fft {
+    pub window:                FftWindowType,
+    pub resolution:            f32,  // Hz per bin, 0 to derive from the dot length
+    pub overlap:               u8,   // percent of each window shared with the next
+    pub subtract_background:   bool, // flatten the passband by a per-bin background estimate
+    pub background_percentile: u8,   // percentile of each bin's level taken as background
+}
+
+impl Default for Fft {
+    fn default() -> Self {
+        Fft {
+            window:                FftWindowType::Hann,
+            resolution:            0.,
+            overlap:               33,
+            subtract_background:   false,
+            background_percentile: 30,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub (crate) struct Filter {
+    pub blanker:           bool, // attenuate columns whose energy spikes (static crashes)
+    pub blanker_threshold: u8,   // dB above the recent column energy
+    pub notch:             bool, // remove persistent narrow carriers (birdies)
+    pub notch_threshold:   u8,   // dB above neighbouring bins
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            blanker:           false,
+            blanker_threshold: 10,
+            notch:             false,
+            notch_threshold:   15,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub (crate) struct Frame {
+    pub duration:   u32, // seconds per grab
+    pub dot_length: u32, // seconds per QRSS dot
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame {
+            duration:   600,
+            dot_length: 3,
         }
     }
 }
@@ -172,6 +641,11 @@ impl Default for Audio {
 pub (crate) struct Image {
     pub brightness:    u8,
     pub contrast:      u8,
+    pub auto_level:    bool, // set levels from the noise floor instead of a fixed level
+    pub palette:       Palette,
+    pub palette_file:  PathBuf, // gradient definition for `Palette::Custom`
+    #[serde(skip)]
+    pub gradient:      Vec<[u8; 3]>, // colors read from `palette_file`
     pub dimensions:    Vec<u32>,
     pub use_window_xy: bool,
 }
@@ -181,6 +655,10 @@ impl Default for Image {
         Image {
             brightness:    50,
             contrast:      50,
+            auto_level:    true,
+            palette:       Palette::Viridis,
+            palette_file:  (*se::full("~/.config/QRuSSt/palette.toml").unwrap()).into(),
+            gradient:      Vec::new(),
             dimensions:    vec![1280, 720],
             use_window_xy: false,
         }
@@ -189,25 +667,76 @@ impl Default for Image {
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub (crate) struct Export {
-    pub path:          PathBuf,
-    pub export_enable: bool,
-    pub single:        bool,
-    pub average:       bool,
-    pub peak:          bool,
-    pub hour:          bool,
-    pub day:           bool,
+    pub path:           PathBuf,
+    pub export_enable:  bool,
+    pub single:         bool,
+    pub average:        bool,
+    pub peak:           bool,
+    pub hour:           bool,
+    pub day:            bool,
+    pub average_frames: u32, // frames combined into average and peak images
+    pub annotate:       bool, // frequency/time scales and a header around exports
 }
 
 impl Default for Export {
     fn default() -> Self {
         Export {
             path: (*se::full("~/.local/share/QRuSSt/export/").unwrap()).into(),
-            export_enable: true,
-            single:        true,
-            average:       true,
-            peak:          true,
-            hour:          true,
-            day:           true,
+            export_enable:  true,
+            single:         true,
+            average:        true,
+            peak:           true,
+            hour:           true,
+            day:            true,
+            average_frames: 6,
+            annotate:       true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub (crate) enum ArchiveFormat {
+    F32,
+    U16,
+    U8,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub (crate) struct Archive {
+    pub enable: bool,
+    pub path:   PathBuf,
+    pub format: ArchiveFormat, // f32 magnitudes, or dB quantized to u16/u8
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Archive {
+            enable: false,
+            path:   (*se::full("~/.local/share/QRuSSt/archive/").unwrap()).into(),
+            format: ArchiveFormat::U16,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub (crate) enum RecordRotation {
+    Frame,
+    Hour,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub (crate) struct Record {
+    pub enable: bool,
+    pub path:   PathBuf,
+    pub rotate: RecordRotation,
+}
+
+impl Default for Record {
+    fn default() -> Self {
+        Record {
+            enable: false,
+            path:   (*se::full("~/.local/share/QRuSSt/recordings/").unwrap()).into(),
+            rotate: RecordRotation::Hour,
         }
     }
 }
@@ -219,6 +748,7 @@ pub (crate) struct Names {
     pub peak:    String,
     pub hour:    String,
     pub day:     String,
+    pub render:  String,
 }
 
 impl Default for Names {
@@ -229,6 +759,22 @@ impl Default for Names {
             peak:    "pk"    .to_string(),
             hour:    "hr"    .to_string(),
             day:     "day"   .to_string(),
+            render:  "render".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub (crate) struct Station {
+    pub callsign: String,
+    pub locator:  String,
+}
+
+impl Default for Station {
+    fn default() -> Self {
+        Station {
+            callsign: String::new(),
+            locator:  String::new(),
         }
     }
 }
@@ -237,14 +783,84 @@ impl Default for Names {
 pub (crate) struct Settings {
     pub verbose:    u8,
     pub config:     PathBuf,
-    pub fft_window: FftWindow,
     pub audio:      Audio,
+    pub radio:      Radio,
+    pub fft:        Fft,
+    pub filter:     Filter,
+    pub frame:      Frame,
     pub image:      Image,
     pub export:     Export,
     pub names:      Names,
+    pub station:    Station,
+    pub record:     Record,
+    pub archive:    Archive,
+}
+
+/// Overrides for what an image shows and where it is saved
+fn image_overrides(mut b: ConfigBuilder<DefaultState>, cli: &clap::ArgMatches) -> Result<ConfigBuilder<DefaultState>, SettingsError> {
+    if let Some(d) = cli.values_of("dimensions") {
+        // requires two args, so direct conversion is ok here
+        let d: Vec<u32> = d.map(|x| x.parse().unwrap()).collect();
+        b = b.set_override::<&str, Vec<i32>>("image.dimensions", vec![d[0] as i32, d[1] as i32])?;
+    }
+
+    if let Some(x) = cli.value_of("brightness") {
+        b = b.set_override::<&str, i8>("image.brightness", x.parse().unwrap())?;
+    }
+
+    if let Some(c) = cli.value_of("contrast") {
+        b = b.set_override::<&str, i8>("image.contrast", c.parse().unwrap())?;
+    }
+
+    // Valid options given in help message, lower case `Palette` names
+    if let Some(p) = cli.value_of("palette") {
+        b = b.set_override("image.palette", format!("{:?}", Palette::from_name(p).unwrap()))?;
+    }
+
+    if let Some(path) = cli.value_of("palette_file") {
+        b = b.set_override("image.palette_file", se::full(path).unwrap().into_owned())?;
+        if !cli.is_present("palette") {
+            b = b.set_override("image.palette", "Custom")?;
+        }
+    }
+
+    if let Some(l) = cli.value_of("levels") {
+        b = b.set_override("image.auto_level", l == "auto")?;
+    }
+
+    if let Some(path) = cli.value_of("export_path") {
+        // Validator already checked expansion. Safe to unwrap.
+        b = b.set_override("export.path", se::full(path).unwrap().into_owned())?;
+    }
+
+    // Value already checked against parse. Safe to unwrap.
+    if let Some(freq) = cli.values_of("frequency_range") {
+        let mut freq: Vec<i32> = freq.map(|x| x.parse().unwrap()).collect();
+        freq.sort_unstable();
+        b = b.set_override::<&str, Vec<i32>>("audio.freq_range", vec![freq[0], freq[1]])?;
+    }
+
+    Ok(b)
 }
 
 impl Settings {
+    /// Write default config to `self.config` if no file exists there yet
+    pub fn create_config(&self) -> Result<bool, SettingsError> {
+        if self.config.exists() {
+            return Ok(false);
+        }
+        if let Some(dir) = self.config.parent() {
+            fs::create_dir_all(dir).map_err(SettingsError::WriteError)?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true).create_new(true)
+            .open(&self.config)
+            .map_err(SettingsError::WriteError)?;
+        file.write_all(DEFAULT_CONFIG.as_bytes())
+            .map_err(SettingsError::WriteError)?;
+        Ok(true)
+    }
+
     pub fn read_config_file(&mut self) -> Result<(), SettingsError> {
         let file = OpenOptions::new()
             .read(true).write(false).create(false)
@@ -253,13 +869,15 @@ impl Settings {
         Ok(())
     }
 
-    pub fn load_config(&mut self, cli: &clap::ArgMatches) -> Result<Self, SettingsError> {
-        let mut b = Config::builder();
-        if self.read_config_file().is_ok() {
-            b = b.add_source(cFile::with_name(&self.config.to_str().unwrap()));
+    /// Merge defaults, config file and CLI arguments, in increasing priority
+    pub fn load_config(&mut self, cli: &clap::ArgMatches, logger: &slog::Logger) -> Result<Self, SettingsError> {
+        // defaults fill in anything missing from an older or partial config file
+        let mut b = Config::builder()
+            .add_source(Config::try_from(&Settings::default())?);
+        if let Err(e) = self.read_config_file() {
+            error!(logger, "Error reading existing config: {:?}", e);
         } else {
-            // XXX: Get logger in here!
-            println!("Error reading existing config.");
+            b = b.add_source(cFile::with_name(&self.config.to_str().unwrap()));
         }
 
         // Parse and save CLI args
@@ -270,49 +888,88 @@ impl Settings {
         })?;
 
         if let Some(c) = cli.value_of("config") {
-            b = b.set_override("config", c)?;
+            b = b.set_override("config", se::full(c).unwrap().into_owned())?;
+        } else {
+            b = b.set_override("config", self.config.to_str().unwrap())?;
         }
 
         if cli.is_present("window") {
             b = b.set_override("image.use_window_xy", true)?;
         }
 
-        if let Some(d) = cli.values_of("dimensions") {
-            // requires two args, so direct conversion is ok here
-            let d: Vec<u32> = d.map(|x| x.parse().unwrap()).collect();
-            b = b.set_override::<&str, Vec<i32>>("image.dimensions", vec![d[0] as i32, d[1] as i32])?;
+        // band presets go first so explicit dial and frequency range arguments win
+        if let Some(band) = cli.value_of("band").and_then(band_preset) {
+            let sideband = cli.value_of("sideband").unwrap_or("usb");
+            let dial = band.dial(if sideband == "lsb" { Sideband::Lsb } else { Sideband::Usb });
+            b = b.set_override("radio.band", band.name)?;
+            b = b.set_override::<&str, i64>("radio.dial_freq", dial as i64)?;
+            b = b.set_override("radio.sideband", sideband.to_uppercase())?;
+            b = b.set_override::<&str, Vec<i32>>("audio.freq_range", band.freq_range())?;
+        }
+
+        // view options, also taken by the render subcommand
+        b = image_overrides(b, cli)?;
+        if let Some(r) = cli.subcommand_matches("render") {
+            b = image_overrides(b, r)?;
         }
 
-        if let Some(x) = cli.value_of("brightness") {
-            b = b.set_override::<&str, i8>("image.brightness", x.parse().unwrap())?;
+        if cli.is_present("export_images") {
+            b = b.set_override("export.export_enable", true)?;
         }
 
-        if let Some(c) = cli.value_of("contrast") {
-            b = b.set_override::<&str, i8>("image.contrast", c.parse().unwrap())?;
+        if cli.is_present("plain") {
+            b = b.set_override("export.annotate", false)?;
         }
 
-        if cli.is_present("export_images") {
-            b = b.set_override("export_images", true)?;
+        if let Some(c) = cli.value_of("callsign") {
+            b = b.set_override("station.callsign", c)?;
         }
 
-        if let Some(path) = cli.value_of("export_path") {
-            if !path.starts_with("file://") {
-                // XXX: unwrap()
-                self.export.path = format!("file://{}", self.export.path.to_str().unwrap()).into();
-            }
-            b = b.set_override("export.path", path)?;
+        if let Some(l) = cli.value_of("locator") {
+            b = b.set_override("station.locator", l)?;
+        }
+
+        if let Some(d) = cli.value_of("dial") {
+            b = b.set_override::<&str, i64>("radio.dial_freq", d.parse().unwrap())?;
+        }
+
+        if let Some(sb) = cli.value_of("sideband") {
+            b = b.set_override("radio.sideband", sb.to_uppercase())?;
+        }
+
+        if cli.is_present("record") {
+            b = b.set_override("record.enable", true)?;
+        }
+
+        if let Some(path) = cli.value_of("record_path") {
+            b = b.set_override("record.path", se::full(path).unwrap().into_owned())?;
+        }
+
+        if let Some(r) = cli.value_of("record_rotate") {
+            b = b.set_override("record.rotate", match r {
+                "frame" => "Frame",
+                _       => "Hour",
+            })?;
+        }
+
+        if cli.is_present("archive") {
+            b = b.set_override("archive.enable", true)?;
+        }
+
+        if let Some(path) = cli.value_of("archive_path") {
+            b = b.set_override("archive.path", se::full(path).unwrap().into_owned())?;
+        }
+
+        if let Some(f) = cli.value_of("archive_format") {
+            b = b.set_override("archive.format", f.to_uppercase())?;
         }
 
         if let Some(dev) = cli.value_of("device") {
             b = b.set_override("audio.device", dev)?;
         }
 
-        // Value already checked against parse. Safe to unwrap.
-        if let Some(freq) = cli.values_of("frequency_range") {
-            let mut freq: Vec<i32> = freq.map(|x| x.parse().unwrap()).collect();
-            freq.sort_unstable();
-            println!("freq: {:?}", &freq);
-            b = b.set_override::<&str, Vec<i32>>("audio.freq_range", vec![freq[0], freq[1]])?;
+        if cli.is_present("iq") {
+            b = b.set_override("audio.iq", true)?;
         }
 
         // Valid options given in help message. Parse directly into u32.
@@ -320,14 +977,73 @@ impl Settings {
             b = b.set_override::<&str, i32>("audio.rate", r.parse().unwrap())?;
         }
 
+        // Valid options given in help message, matching `AudioFormat` names
+        if let Some(f) = cli.value_of("format") {
+            b = b.set_override("audio.format", f)?;
+        }
+
+        // Valid options given in help message, lower case `FftWindowType` names
+        if let Some(w) = cli.value_of("fft_window") {
+            b = b.set_override("fft.window", format!("{:?}", FftWindowType::from_name(w).unwrap()))?;
+        }
+
+        if let Some(r) = cli.value_of("resolution") {
+            b = b.set_override::<&str, f64>("fft.resolution", r.parse().unwrap())?;
+        }
+
+        if let Some(o) = cli.value_of("overlap") {
+            b = b.set_override::<&str, i32>("fft.overlap", o.parse().unwrap())?;
+        }
+
+        if cli.is_present("background") {
+            b = b.set_override("fft.subtract_background", true)?;
+        }
+
+        if cli.is_present("blanker") {
+            b = b.set_override("filter.blanker", true)?;
+        }
+
+        if cli.is_present("notch") {
+            b = b.set_override("filter.notch", true)?;
+        }
+
+        if let Some(p) = cli.value_of("bg_percentile") {
+            b = b.set_override::<&str, i32>("fft.background_percentile", p.parse().unwrap())?;
+        }
+
+        if let Some(t) = cli.value_of("frame_duration") {
+            b = b.set_override::<&str, i32>("frame.duration", t.parse().unwrap())?;
+        }
+
+        if let Some(l) = cli.value_of("dot_length") {
+            b = b.set_override::<&str, i32>("frame.dot_length", l.parse().unwrap())?;
+        }
+
         // Read files and finalize config for use
         let s = b.build()?;
-        s.try_deserialize().map_err(SettingsError::ConfigError)
+        let mut set: Self = s.try_deserialize().map_err(SettingsError::ConfigError)?;
+
+        // config files may use `~` in paths
+        set.export.path = (*se::full(set.export.path.to_str().unwrap()).unwrap()).into();
+        set.record.path = (*se::full(set.record.path.to_str().unwrap()).unwrap()).into();
+        set.archive.path = (*se::full(set.archive.path.to_str().unwrap()).unwrap()).into();
+        set.image.palette_file = (*se::full(set.image.palette_file.to_str().unwrap()).unwrap()).into();
+
+        if set.image.palette == Palette::Custom {
+            match load_gradient(&set.image.palette_file) {
+                Ok(g) => set.image.gradient = g,
+                Err(e) => error!(logger, "Error reading palette {:?}: {:?}", set.image.palette_file, e),
+            }
+        }
+
+        // input file is per run and never saved
+        set.audio.input_file = cli.value_of("input_file").map(|f| (*se::full(f).unwrap()).into());
+        Ok(set)
     }
 
     pub fn write_config(&self) -> Result<(), SettingsError> {
         let mut file = OpenOptions::new()
-            .write(true).create(true)
+            .write(true).create(true).truncate(true)
             .open(&self.config)
             .map_err(SettingsError::WriteError)?;
         let coded = toml::to_string(self)
@@ -343,11 +1059,46 @@ impl Default for Settings {
         Settings {
             verbose:    0,
             config:     (*se::full("~/.config/QRuSSt/config.toml").unwrap()).into(),
-            fft_window: FftWindow::default(),
             audio:      Audio::default(),
+            radio:      Radio::default(),
+            fft:        Fft::default(),
+            filter:     Filter::default(),
+            frame:      Frame::default(),
             image:      Image::default(),
             export:     Export::default(),
             names:      Names::default(),
+            station:    Station::default(),
+            record:     Record::default(),
+            archive:    Archive::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn band_presets_show_their_window_on_either_sideband() {
+        for band in BANDS.iter() {
+            let range = band.freq_range();
+            assert!(0 < range[0] && range[0] < range[1] && range[1] <= 3000, "{}: {:?}", band.name, range);
+
+            let margin = ((band.window[1] - band.window[0]) / 4) as i64;
+            let (low, high) = (band.window[0] as i64 - margin, band.window[1] as i64 + margin);
+            for &(sideband, edges) in &[(Sideband::Usb, [low, high]), (Sideband::Lsb, [high, low])] {
+                let radio = Radio {
+                    dial_freq: band.dial(sideband),
+                    sideband,
+                    band:      band.name.to_string(),
+                };
+                // the same audio window on both sidebands, LSB running backwards in RF
+                let rf = [radio.rf(range[0] as i64), radio.rf(range[1] as i64)];
+                assert_eq!(rf, [Some(edges[0]), Some(edges[1])], "{} {:?}", band.name, sideband);
+                assert_eq!(radio.inverted(), sideband == Sideband::Lsb);
+            }
         }
+        assert_eq!(band_preset("137k"), band_preset("2200M"));
+        assert!(Radio::default().rf(1000).is_none());
     }
 }
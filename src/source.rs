@@ -0,0 +1,255 @@
+/// Audio file input for offline processing
+///
+/// WAV and FLAC recordings are decoded at their native sample rate to f32 samples in [-1, 1].
+
+
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use hound;
+use claxon;
+
+
+/// Number of frames handed over per chunk
+const CHUNK_FRAMES: usize = 4096;
+
+#[derive(Debug)]
+pub (crate) enum SourceError {
+    WavError(hound::Error),    // WAV decode error
+    FlacError(claxon::Error),  // FLAC decode error
+    FormatError(String),       // unsupported file type
+}
+
+impl From<hound::Error> for SourceError {
+    fn from(e: hound::Error) -> Self {
+        SourceError::WavError(e)
+    }
+}
+
+impl From<claxon::Error> for SourceError {
+    fn from(e: claxon::Error) -> Self {
+        SourceError::FlacError(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileType {
+    Wav,
+    Flac,
+}
+
+fn file_type(path: &Path) -> Result<FileType, SourceError> {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+        Some("wav")  => Ok(FileType::Wav),
+        Some("flac") => Ok(FileType::Flac),
+        _ => Err(SourceError::FormatError(format!("Unsupported audio file: {:?}", path))),
+    }
+}
+
+/// Stream parameters of an audio file
+#[derive(Debug, Clone, PartialEq)]
+pub (crate) struct FileInfo {
+    pub sample_rate: u32,
+    pub channels:    u16,
+    pub frames:      u64,
+}
+
+/// UTC time stamped into the name of a recording made here: `rec_<YYYYmmdd>_<HHMMSS>[_<n>]`
+fn name_time(path: &Path) -> Option<DateTime<Utc>> {
+    let stem = path.file_stem()?.to_str()?.strip_prefix("rec_")?;
+    let (stamp, rest) = (stem.get(..15)?, &stem[15..]);
+    if !(rest.is_empty() || rest.strip_prefix('_')?.chars().all(|c| c.is_ascii_digit())) {
+        return None;
+    }
+    NaiveDateTime::parse_from_str(stamp, "%Y%m%d_%H%M%S").ok().map(|t| Utc.from_utc_datetime(&t))
+}
+
+impl FileInfo {
+    /// Best guess at the UTC time of the first sample
+    ///
+    /// Recordings made here are named after their first sample, to the second. Otherwise the last
+    /// modification marks the end, as recordings are written while they are captured; copies
+    /// lose that.
+    pub (crate) fn start_time(&self, path: &Path) -> DateTime<Utc> {
+        if let Some(start) = name_time(path) {
+            return start;
+        }
+        let end: DateTime<Utc> = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map(DateTime::from)
+            .unwrap_or_else(|_| Utc::now());
+        end - Duration::milliseconds((self.frames * 1000 / self.sample_rate as u64) as i64)
+    }
+}
+
+/// Read stream parameters without decoding
+pub (crate) fn probe(path: &Path) -> Result<FileInfo, SourceError> {
+    match file_type(path)? {
+        FileType::Wav => {
+            let reader = hound::WavReader::open(path)?;
+            let spec = reader.spec();
+            Ok(FileInfo {
+                sample_rate: spec.sample_rate,
+                channels:    spec.channels,
+                frames:      reader.duration() as u64,
+            })
+        },
+        FileType::Flac => {
+            let reader = claxon::FlacReader::open(path)?;
+            let info = reader.streaminfo();
+            Ok(FileInfo {
+                sample_rate: info.sample_rate,
+                channels:    info.channels as u16,
+                frames:      info.samples.unwrap_or(0),
+            })
+        },
+    }
+}
+
+/// Decode `path`, handing interleaved f32 samples to `f` a chunk at a time
+pub (crate) fn read_file<F: FnMut(&[f32])>(path: &Path, mut f: F) -> Result<(), SourceError> {
+    let info = probe(path)?;
+    let size = CHUNK_FRAMES * info.channels as usize;
+    let mut chunk: Vec<f32> = Vec::with_capacity(size);
+
+    let mut push = |s: f32| {
+        chunk.push(s);
+        if chunk.len() == size {
+            f(&chunk);
+            chunk.clear();
+        }
+    };
+
+    match file_type(path)? {
+        FileType::Wav => {
+            let mut reader = hound::WavReader::open(path)?;
+            let spec = reader.spec();
+            match spec.sample_format {
+                hound::SampleFormat::Float => {
+                    for s in reader.samples::<f32>() {
+                        push(s?);
+                    }
+                },
+                hound::SampleFormat::Int => {
+                    let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+                    for s in reader.samples::<i32>() {
+                        push(s? as f32 / scale);
+                    }
+                },
+            }
+        },
+        FileType::Flac => {
+            let mut reader = claxon::FlacReader::open(path)?;
+            let scale = (1_i64 << (reader.streaminfo().bits_per_sample - 1)) as f32;
+            for s in reader.samples() {
+                push(s? as f32 / scale);
+            }
+        },
+    }
+    drop(push);
+
+    if !chunk.is_empty() {
+        f(&chunk);
+    }
+    Ok(())
+}
+
+/// Average interleaved channels down to mono
+pub (crate) fn downmix(samples: &[f32], channels: u16) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+    samples.chunks(channels as usize)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use super::super::analyzer::Analyzer;
+    use super::super::render;
+    use super::super::settings::Settings;
+    use super::super::spectrum::History;
+
+    #[test]
+    fn wav_tone_lands_on_its_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tone.wav");
+        let spec = hound::WavSpec {
+            channels:        1,
+            sample_rate:     8000,
+            bits_per_sample: 16,
+            sample_format:   hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..30 * spec.sample_rate {
+            let s = (2. * PI * 1000. * i as f64 / spec.sample_rate as f64).sin();
+            writer.write_sample((s * 16000.) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        // 5 Hz per row, the tone on row 100 of a one minute frame
+        let mut set = Settings::default();
+        set.audio.rate = probe(&path).unwrap().sample_rate;
+        set.audio.freq_range = vec![500, 1500];
+        set.image.dimensions = vec![120, 200];
+        set.frame.duration = 60;
+
+        let anchor = Utc.timestamp_opt(1_609_502_400, 0).unwrap();
+        let mut analyzer = Analyzer::new(&set, anchor);
+        let mut history = History::new(set.frame.duration, set.image.dimensions[0], &set.audio.freq_range);
+        read_file(&path, |d| analyzer.process(d, |c| history.push(c))).unwrap();
+
+        let frame = history.current().unwrap();
+        assert_eq!(frame.start, anchor);
+        let grid = render::grid(&frame, &set.audio.freq_range, &set.image.dimensions, false);
+
+        let (w, h) = (grid.width as usize, grid.height as usize);
+        let filled: Vec<usize> = (0..w).filter(|&x| grid.data[x] > 0.).collect();
+        // 30 s of a 60 s frame, less half an FFT window at each end
+        assert!(filled.len() > w / 2 - 4 && filled.len() <= w / 2, "{} columns filled", filled.len());
+        for x in filled {
+            let peak = (0..h).max_by(|&a, &b| grid.data[a * w + x].partial_cmp(&grid.data[b * w + x]).unwrap()).unwrap();
+            assert!((peak as i64 - 100).abs() <= 1, "column {}: peak on row {}", x, peak);
+        }
+    }
+
+    #[test]
+    fn start_time_from_recording_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = hound::WavSpec {
+            channels:        2,
+            sample_rate:     8000,
+            bits_per_sample: 16,
+            sample_format:   hound::SampleFormat::Int,
+        };
+        let stamped = Utc.timestamp_opt(1_609_502_399, 0).unwrap();
+        for (name, start) in &[
+            ("rec_20210101_115959.wav",   Some(stamped)),
+            ("rec_20210101_115959_2.wav", Some(stamped)),
+            ("rec_20210101_1159.wav",     None),
+            ("tone_20210101_115959.wav",  None),
+        ] {
+            let path = dir.path().join(name);
+            let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+            for _ in 0..2 * 8000 * 2 {
+                writer.write_sample(0_i16).unwrap();
+            }
+            writer.finalize().unwrap();
+
+            let info = probe(&path).unwrap();
+            match start {
+                Some(start) => assert_eq!(info.start_time(&path), *start, "{}", name),
+                // just written: two seconds of audio ending now
+                None => {
+                    let age = Utc::now() - info.start_time(&path);
+                    assert!(age >= Duration::seconds(2) && age < Duration::seconds(10), "{}: {}", name, age);
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,708 @@
+/// Timestamped spectral data shared between the FFT and image threads
+
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+
+/// One FFT result, tagged with the UTC time at the center of its window
+///
+/// Audio spectra span 0 Hz to Nyquist, I/Q spectra -Nyquist to Nyquist; a column holds
+/// `total_bins` of them, or the part from `first_bin` on once cropped. Each column carries the
+/// stream parameters it was computed with, so history stays valid across FFT rebuilds, and
+/// stands for the `interval` around its time until the neighbouring columns take over.
+#[derive(Debug, Clone)]
+pub (crate) struct Column {
+    pub time:        DateTime<Utc>,
+    pub interval:    Duration,
+    pub sample_rate: u32,
+    pub iq:          bool,
+    pub total_bins:  usize,    // bins in the whole spectrum
+    pub first_bin:   usize,    // index in the whole spectrum of `bins[0]`
+    pub bins:        Vec<f32>,
+}
+
+impl Column {
+    /// Keep only the bins covering `freq_range`, and one more on either side for interpolation
+    ///
+    /// A long FFT has bins far beyond the range shown, and a frame keeps hundreds of columns.
+    pub (crate) fn crop(&mut self, freq_range: &[i32]) {
+        let axis = FreqAxis::of(self);
+        let (first, end) = (self.first_bin as i64, (self.first_bin + self.bins.len()) as i64);
+        let a = (axis.bin(freq_range[0] as f32).floor() as i64 - 1).max(first).min(end);
+        let b = (axis.bin(freq_range[1] as f32).ceil() as i64 + 2).max(a).min(end);
+        self.bins = self.bins[(a - first) as usize..(b - first) as usize].to_vec();
+        self.first_bin = a as usize;
+    }
+
+    /// Value of image row `row`; bins cropped away read as 0
+    pub (crate) fn sample(&self, row: &RowSample) -> f32 {
+        row.sample(&self.bins, self.first_bin)
+    }
+}
+
+/// Power in dB of an FFT magnitude
+pub (crate) fn db(magnitude: f32) -> f32 {
+    20. * magnitude.max(1e-12).log10()
+}
+
+/// Time of sample number `sample` in a stream started at `anchor`
+pub (crate) fn sample_time(anchor: DateTime<Utc>, sample: u64, sample_rate: u32) -> DateTime<Utc> {
+    anchor + Duration::microseconds((sample * 1_000_000 / sample_rate as u64) as i64)
+}
+
+/// Start of the UTC-aligned frame containing `time`
+///
+/// Frames are counted from midnight UTC, so any duration dividing a day (10 min, 1 h, ...) starts
+/// on the same wall-clock boundaries every day.
+pub (crate) fn frame_start(time: DateTime<Utc>, duration: u32) -> DateTime<Utc> {
+    let secs = time.timestamp();
+    let day = secs - secs.rem_euclid(86400);
+    let offset = (secs - day) - (secs - day) % duration as i64;
+    Utc.timestamp_opt(day + offset, 0).unwrap()
+}
+
+/// Spacing of time axis ticks in seconds, aiming for no more than a dozen per frame
+pub (crate) fn tick_interval(duration: u32) -> u32 {
+    const STEPS: [u32; 12] = [10, 30, 60, 120, 300, 600, 900, 1800, 3600, 7200, 10800, 21600];
+    *STEPS.iter()
+        .find(|&&s| duration / s <= 12)
+        .unwrap_or(&STEPS[STEPS.len() - 1])
+}
+
+/// FFT geometry derived from the wanted frequency resolution and window overlap
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) struct FftPlan {
+    pub window_size: u32, // samples analyzed per column
+    pub fft_size:    u32, // window plus zero padding, a power of two
+    pub shift_size:  u32, // samples between consecutive columns
+}
+
+impl FftPlan {
+    /// `resolution` in Hz per bin, or 0 to pick one from `dot_length`; `overlap` in percent
+    pub (crate) fn new(sample_rate: u32, resolution: f32, overlap: u8, dot_length: u32) -> Self {
+        // by default analyze half a dot at a time so dots and gaps stay apart in time, which
+        //   gives a resolution of ~2 / dot_length Hz
+        let resolution = if resolution > 0. { resolution } else { 2. / dot_length.max(1) as f32 };
+        let window_size = ((sample_rate as f32 / resolution).round() as u32).max(2);
+        let overlap = overlap.min(95) as u64;
+        FftPlan {
+            window_size,
+            // zero padding up to the next power of two keeps bins at least as fine as asked
+            fft_size:   window_size.next_power_of_two(),
+            shift_size: ((window_size as u64 * (100 - overlap) / 100) as u32).max(1),
+        }
+    }
+
+    pub (crate) fn zero_padding(&self) -> u32 {
+        self.fft_size - self.window_size
+    }
+
+    /// Spacing of FFT bins in Hz
+    pub (crate) fn hz_per_bin(&self, sample_rate: u32) -> f32 {
+        sample_rate as f32 / self.fft_size as f32
+    }
+
+    /// Time between consecutive columns
+    pub (crate) fn column_interval(&self, sample_rate: u32) -> Duration {
+        Duration::microseconds((self.shift_size as u64 * 1_000_000 / sample_rate as u64) as i64)
+    }
+}
+
+/// How one image row is read from a column of bins
+#[derive(Debug, Clone, PartialEq)]
+pub (crate) enum RowSample {
+    Pool(usize, usize), // row spans bins `.0..=.1`, keep the strongest
+    Lerp(usize, f32),   // row falls between bin `.0` and the next, this far along
+    Empty,              // row lies outside the spectrum
+}
+
+impl RowSample {
+    /// Value of the row from `bins`, which start at bin `first` of the spectrum
+    pub (crate) fn sample(&self, bins: &[f32], first: usize) -> f32 {
+        let bin = |i: usize| i.checked_sub(first).and_then(|i| bins.get(i)).copied();
+        match *self {
+            RowSample::Pool(a, b) => (a..=b).filter_map(bin).fold(0_f32, f32::max),
+            RowSample::Lerp(i, f) => match (bin(i), bin(i + 1)) {
+                (Some(v), Some(next)) => v * (1. - f) + next * f,
+                (Some(v), None)       => v,
+                (None, _)             => 0.,
+            },
+            RowSample::Empty => 0.,
+        }
+    }
+}
+
+/// Frequencies of the bins of an FFT column
+///
+/// Audio columns hold the positive half of the spectrum, bin 0 at 0 Hz. I/Q columns hold the
+/// whole spectrum with negative frequencies first, the middle bin at 0 Hz.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) struct FreqAxis {
+    pub bins:        usize,
+    pub sample_rate: u32,
+    pub iq:          bool,
+}
+
+impl FreqAxis {
+    pub (crate) fn new(bins: usize, sample_rate: u32, iq: bool) -> Self {
+        FreqAxis { bins, sample_rate, iq }
+    }
+
+    /// Axis of an FFT column
+    pub (crate) fn of(column: &Column) -> Self {
+        FreqAxis::new(column.total_bins, column.sample_rate, column.iq)
+    }
+
+    /// Spacing of bins in Hz
+    pub (crate) fn hz_per_bin(&self) -> f32 {
+        if self.iq {
+            self.sample_rate as f32 / self.bins as f32
+        } else {
+            self.sample_rate as f32 / 2. / self.bins as f32
+        }
+    }
+
+    /// Index of the bin at 0 Hz
+    fn zero_bin(&self) -> f32 {
+        if self.iq { (self.bins / 2) as f32 } else { 0. }
+    }
+
+    /// Fractional bin index of `freq`, which may fall outside `0..bins`
+    pub (crate) fn bin(&self, freq: f32) -> f32 {
+        freq / self.hz_per_bin() + self.zero_bin()
+    }
+
+    /// How to read each of `rows` image rows (top to bottom) evenly covering `freq_range`
+    ///
+    /// Rows spanning a bin or more keep the strongest bin so narrow traces don't vanish when
+    /// zoomed out; narrower rows interpolate between neighbouring bins.
+    pub (crate) fn rows(&self, freq_range: &[i32], rows: u32) -> Vec<RowSample> {
+        let (freq_min, freq_max) = (freq_range[0] as f32, freq_range[1] as f32);
+        let row_hz = (freq_max - freq_min) / rows.max(1) as f32;
+        let last = self.bins as f32 - 1.;
+
+        (0..rows).map(|y| {
+            // highest frequency at the top of the image
+            let hi = self.bin(freq_max - row_hz * y as f32);
+            let lo = self.bin(freq_max - row_hz * (y + 1) as f32);
+            let center = (lo + hi) / 2.;
+
+            if self.bins == 0 || hi < -0.5 || lo > last + 0.5 {
+                RowSample::Empty
+            } else if hi - lo >= 1. {
+                let a = lo.ceil().max(0.).min(last) as usize;
+                let b = hi.floor().max(0.).min(last) as usize;
+                RowSample::Pool(a, b.max(a))
+            } else {
+                let c = center.max(0.).min(last);
+                RowSample::Lerp(c.floor() as usize, c.fract())
+            }
+        }).collect()
+    }
+}
+
+/// Change in dB a background estimate can follow within one frame
+const BACKGROUND_SPAN_DB: f32 = 30.;
+
+/// Running per-bin estimate of the band background, used to flatten the receiver passband
+///
+/// Each bin tracks a percentile of its level in dB: the estimate steps up when a new value lies
+/// above it and down when below, with steps weighted so it settles where `percentile` of values
+/// fall below. Steps are sized to follow a drift of `BACKGROUND_SPAN_DB` over a frame, so
+/// traces lasting a few dots barely move it.
+#[derive(Debug, Clone)]
+pub (crate) struct Background {
+    quantile: f32,
+    step:     f32,
+    levels:   Vec<f32>,
+}
+
+impl Background {
+    pub (crate) fn new(percentile: u8, columns_per_frame: u32) -> Self {
+        Background {
+            quantile: percentile.min(100) as f32 / 100.,
+            step:     BACKGROUND_SPAN_DB / columns_per_frame.max(1) as f32,
+            levels:   Vec::new(),
+        }
+    }
+
+    /// Update the estimate with the magnitudes in `bins`, then divide it out of them
+    ///
+    /// The band's average background is kept, so the overall level stays comparable.
+    pub (crate) fn subtract(&mut self, bins: &mut [f32]) {
+        let levels: Vec<f32> = bins.iter().map(|&b| db(b)).collect();
+        if self.levels.len() != bins.len() {
+            self.levels = levels.clone();
+        }
+        for (l, &v) in self.levels.iter_mut().zip(&levels) {
+            if v > *l {
+                *l += self.step * self.quantile;
+            } else {
+                *l -= self.step * (1. - self.quantile);
+            }
+        }
+
+        let mean = self.levels.iter().sum::<f32>() / self.levels.len().max(1) as f32;
+        for ((b, &v), &l) in bins.iter_mut().zip(&levels).zip(&self.levels) {
+            *b = 10_f32.powf((v - l + mean) / 20.);
+        }
+    }
+}
+
+/// Weight of each new column in the impulse blanker's reference energy
+const BLANKER_SMOOTHING: f32 = 0.05;
+
+/// Attenuates columns whose energy spikes above the recent level, like static crashes that
+/// would otherwise draw full-height stripes
+#[derive(Debug, Clone)]
+pub (crate) struct Blanker {
+    threshold: f32,
+    reference: Option<f32>,
+}
+
+impl Blanker {
+    /// Columns more than `threshold` dB above the reference energy are blanked
+    pub (crate) fn new(threshold: u8) -> Self {
+        Blanker {
+            threshold: threshold as f32,
+            reference: None,
+        }
+    }
+
+    /// Scale `bins` back to the reference energy if they spike; returns whether they did
+    pub (crate) fn blank(&mut self, bins: &mut [f32]) -> bool {
+        if bins.is_empty() {
+            return false;
+        }
+        let energy = db((bins.iter().map(|b| b * b).sum::<f32>() / bins.len() as f32).sqrt());
+        let reference = *self.reference.get_or_insert(energy);
+
+        // impulses only nudge the reference, so a lasting rise in band noise is still followed
+        self.reference = Some(reference + BLANKER_SMOOTHING * (energy.min(reference + self.threshold) - reference));
+
+        if energy - reference > self.threshold {
+            let gain = 10_f32.powf((reference - energy) / 20.);
+            bins.iter_mut().for_each(|b| *b *= gain);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Bins on either side compared against when looking for carriers
+const NOTCH_RADIUS: usize = 16;
+
+/// Share of time a bin must stand out to count as a carrier
+const NOTCH_PERSISTENCE: f32 = 0.9;
+
+/// Removes narrow carriers (birdies) that stand out from neighbouring bins nearly all the time
+///
+/// Keyed QRSS signals come and go, so they stay below the persistence a steady carrier reaches.
+#[derive(Debug, Clone)]
+pub (crate) struct Notch {
+    threshold:   f32,
+    smoothing:   f32,
+    persistence: Vec<f32>,
+}
+
+impl Notch {
+    /// Bins `threshold` dB above their neighbours count as standing out; persistence is averaged
+    /// over about a quarter frame
+    pub (crate) fn new(threshold: u8, columns_per_frame: u32) -> Self {
+        Notch {
+            threshold:   threshold as f32,
+            smoothing:   (4. / columns_per_frame.max(1) as f32).min(1.),
+            persistence: Vec::new(),
+        }
+    }
+
+    /// Replace carrier bins in `bins` with the level of their neighbours; returns how many
+    pub (crate) fn notch(&mut self, bins: &mut [f32]) -> usize {
+        let n = bins.len();
+        if self.persistence.len() != n {
+            self.persistence = vec![0.; n];
+        }
+        let levels: Vec<f32> = bins.iter().map(|&b| db(b)).collect();
+
+        // running sums give each bin's neighbourhood mean in one pass
+        let mut sums = Vec::with_capacity(n + 1);
+        sums.push(0_f64);
+        for &l in &levels {
+            sums.push(sums.last().unwrap() + l as f64);
+        }
+
+        let mut notched = 0;
+        for i in 0..n {
+            let (a, b) = (i.saturating_sub(NOTCH_RADIUS), (i + NOTCH_RADIUS + 1).min(n));
+            if b - a < 2 {
+                continue;
+            }
+            let around = ((sums[b] - sums[a] - levels[i] as f64) / (b - a - 1) as f64) as f32;
+
+            let hit = if levels[i] - around > self.threshold { 1. } else { 0. };
+            let p = &mut self.persistence[i];
+            *p += self.smoothing * (hit - *p);
+
+            if *p > NOTCH_PERSISTENCE {
+                bins[i] = 10_f32.powf(around / 20.);
+                notched += 1;
+            }
+        }
+        notched
+    }
+}
+
+/// Finished frames kept for the image thread before the oldest is dropped
+const MAX_FINISHED: usize = 4;
+
+/// Columns of one UTC-aligned frame, oldest first
+///
+/// Columns are shared, so taking a frame out of the history copies pointers only.
+#[derive(Debug, Clone)]
+pub (crate) struct Frame {
+    pub start:    DateTime<Utc>,
+    pub duration: u32,
+    pub columns:  Vec<Arc<Column>>,
+}
+
+/// Frame being filled, one slot per image pixel
+#[derive(Debug)]
+struct Slots {
+    start: DateTime<Utc>,
+    slots: Vec<Option<Arc<Column>>>,
+}
+
+/// Spectral history shared by the FFT and image threads, bounded in size
+///
+/// Only the frame being captured is kept, with at most one column per horizontal pixel, cropped
+/// to the frequency range shown; columns landing on the same pixel are combined keeping the
+/// strongest value of each bin. When a column
+/// belongs to another frame, or the frame layout changes, the current frame rolls over: it is
+/// queued as finished for the image thread, and a new one starts.
+#[derive(Debug)]
+pub (crate) struct History {
+    duration:   u32,
+    width:      u32,
+    freq_range: Vec<i32>,
+    current:    Option<Slots>,
+    finished:   VecDeque<Frame>,
+    dropped:    usize,
+}
+
+impl History {
+    pub (crate) fn new(duration: u32, width: u32, freq_range: &[i32]) -> Self {
+        History {
+            duration,
+            width,
+            freq_range: freq_range.to_vec(),
+            current:    None,
+            finished:   VecDeque::new(),
+            dropped:    0,
+        }
+    }
+
+    /// Set frame duration, pixel width and the frequency range kept of new columns; a change of
+    /// duration or width rolls the current frame over
+    pub (crate) fn configure(&mut self, duration: u32, width: u32, freq_range: &[i32]) {
+        if (duration, width) != (self.duration, self.width) {
+            self.roll_over();
+            self.duration = duration;
+            self.width = width;
+        }
+        self.freq_range = freq_range.to_vec();
+    }
+
+    fn roll_over(&mut self) {
+        if let Some(frame) = self.take_current() {
+            self.finished.push_back(frame);
+            // nobody is collecting finished frames fast enough, forget the oldest
+            while self.finished.len() > MAX_FINISHED {
+                self.finished.pop_front();
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// Whether a frame finishing now would push out one not yet taken
+    pub (crate) fn is_full(&self) -> bool {
+        self.finished.len() >= MAX_FINISHED
+    }
+
+    /// Number of finished frames forgotten before being taken, since the last call
+    pub (crate) fn take_dropped(&mut self) -> usize {
+        std::mem::replace(&mut self.dropped, 0)
+    }
+
+    pub (crate) fn push(&mut self, mut column: Column) {
+        column.crop(&self.freq_range);
+        let start = frame_start(column.time, self.duration);
+        if self.current.as_ref().map_or(false, |c| c.start != start) {
+            self.roll_over();
+        }
+        let width = self.width.max(1) as usize;
+        let current = self.current.get_or_insert_with(|| Slots {
+            start,
+            slots: vec![None; width],
+        });
+
+        let ms = (column.time - start).num_milliseconds().max(0);
+        let x = ((ms * width as i64 / (self.duration as i64 * 1000)) as usize).min(width - 1);
+        match &mut current.slots[x] {
+            Some(c) if FreqAxis::of(c) == FreqAxis::of(&column)
+                    && (c.first_bin, c.bins.len()) == (column.first_bin, column.bins.len()) => {
+                let c = Arc::make_mut(c);
+                for (a, b) in c.bins.iter_mut().zip(&column.bins) {
+                    *a = a.max(*b);
+                }
+            },
+            slot => *slot = Some(Arc::new(column)),
+        }
+    }
+
+    /// Snapshot of the frame being captured
+    pub (crate) fn current(&self) -> Option<Frame> {
+        self.current.as_ref().map(|c| Frame {
+            start:    c.start,
+            duration: self.duration,
+            columns:  c.slots.iter().flatten().cloned().collect(),
+        })
+    }
+
+    /// Remove the frame being captured, to finish it early (at shutdown)
+    pub (crate) fn take_current(&mut self) -> Option<Frame> {
+        let frame = self.current();
+        self.current = None;
+        frame
+    }
+
+    /// Remove the frames finished since the last call, oldest first
+    pub (crate) fn take_finished(&mut self) -> Vec<Frame> {
+        self.finished.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use chrono::{Duration, TimeZone, Utc};
+    use rustfft::{FftPlanner, num_complex::Complex};
+
+    use super::*;
+    use super::super::windows;
+
+    /// Column of a Hann-windowed tone at `freq`, laid out like the analyzer's
+    fn tone_column(freq: f64, sample_rate: u32, resolution: f32, iq: bool) -> Column {
+        let plan = FftPlan::new(sample_rate, resolution, 0, 1);
+        let n = plan.fft_size as usize;
+        let mut buffer: Vec<Complex<f32>> = windows::hann(plan.window_size as usize).iter()
+            .enumerate()
+            .map(|(i, w)| {
+                let phase = 2. * PI * freq * i as f64 / sample_rate as f64;
+                let q = if iq { phase.sin() } else { 0. };
+                Complex::new(phase.cos() as f32, q as f32) * w
+            })
+            .collect();
+        buffer.resize(n, Complex::new(0., 0.));
+        FftPlanner::new().plan_fft_forward(n).process(&mut buffer);
+        if iq {
+            buffer.rotate_left(n / 2);
+        } else {
+            buffer.truncate(n / 2);
+        }
+        Column {
+            time:        Utc::now(),
+            interval:    Duration::seconds(1),
+            sample_rate,
+            iq,
+            total_bins:  buffer.len(),
+            first_bin:   0,
+            bins:        buffer.iter().map(|c| c.norm()).collect(),
+        }
+    }
+
+    fn argmax(values: impl Iterator<Item = f32>) -> usize {
+        values.enumerate()
+            .fold((0, f32::MIN), |m, (i, v)| if v > m.1 { (i, v) } else { m })
+            .0
+    }
+
+    /// Check that the peak of a tone lands on its bin and on the image row covering it
+    fn assert_tone_row(freq: f64, sample_rate: u32, resolution: f32, iq: bool, freq_range: [i32; 2], rows: u32) {
+        let column = tone_column(freq, sample_rate, resolution, iq);
+        let axis = FreqAxis::of(&column);
+
+        let peak_bin = argmax(column.bins.iter().cloned());
+        assert!((axis.bin(freq as f32) - peak_bin as f32).abs() <= 0.5,
+            "{} Hz: peak in bin {}, axis says {}", freq, peak_bin, axis.bin(freq as f32));
+
+        let row_hz = (freq_range[1] - freq_range[0]) as f64 / rows as f64;
+        let expected = ((freq_range[1] as f64 - freq) / row_hz) as i64;
+        // the peak bin may sit up to half a bin off the tone
+        let tolerance = (axis.hz_per_bin() as f64 / row_hz / 2.).ceil() as i64 + 1;
+        let layout = axis.rows(&freq_range, rows);
+        let peak_row = argmax(layout.iter().map(|r| column.sample(r)));
+        assert!((peak_row as i64 - expected).abs() <= tolerance,
+            "{} Hz: peak on row {}, expected {} +/- {}", freq, peak_row, expected, tolerance);
+
+        // cropping to the range keeps every row as it was
+        let mut cropped = column.clone();
+        cropped.crop(&freq_range);
+        let range_bins = (freq_range[1] - freq_range[0]) as f32 / axis.hz_per_bin();
+        assert!(cropped.bins.len() as f32 <= range_bins + 5., "{} bins kept", cropped.bins.len());
+        assert_eq!(FreqAxis::of(&cropped), axis);
+        for r in &layout {
+            assert_eq!(cropped.sample(r), column.sample(r));
+        }
+    }
+
+    #[test]
+    fn audio_tone_rows() {
+        // rows spanning several bins, pooled
+        assert_tone_row(1234.5, 48000, 1., false, [100, 2800], 270);
+        assert_tone_row(450., 8000, 0.5, false, [100, 2800], 270);
+        // rows narrower than a bin, interpolated
+        assert_tone_row(1500.3, 8000, 0.5, false, [1490, 1510], 200);
+        assert_tone_row(1500.3, 48000, 1., false, [1490, 1510], 200);
+    }
+
+    #[test]
+    fn iq_tone_rows() {
+        assert_tone_row(-3000., 48000, 2., true, [-6000, 6000], 600);
+        assert_tone_row(5000., 48000, 2., true, [-6000, 6000], 600);
+        assert_tone_row(-1234., 12000, 4., true, [-1500, 500], 100);
+        assert_tone_row(-20.7, 12000, 4., true, [-30, 30], 120);
+    }
+
+    fn magnitude(level_db: f32) -> f32 {
+        10_f32.powf(level_db / 20.)
+    }
+
+    #[test]
+    fn background_flattens_passband_and_keeps_tones() {
+        let mut background = Background::new(30, 100);
+        background.subtract(&mut vec![1.; 64]);
+        // follow the passband tilting by 20 dB across the band
+        let passband: Vec<f32> = (0..64).map(|i| i as f32 * 20. / 63.).collect();
+        for _ in 0..400 {
+            let mut bins: Vec<f32> = passband.iter().map(|&l| magnitude(l)).collect();
+            background.subtract(&mut bins);
+        }
+
+        // a tone 40 dB above the passband
+        let mut bins: Vec<f32> = passband.iter().map(|&l| magnitude(l)).collect();
+        bins[20] = magnitude(passband[20] + 40.);
+        background.subtract(&mut bins);
+
+        let levels: Vec<f32> = bins.iter().map(|&b| db(b)).collect();
+        let mean = passband.iter().sum::<f32>() / passband.len() as f32;
+        for (i, l) in levels.iter().enumerate().filter(|(i, _)| *i != 20) {
+            assert!((l - mean).abs() < 1., "bin {} at {} dB, background at {} dB", i, l, mean);
+        }
+        assert!((levels[20] - mean - 40.).abs() < 1., "tone at {} dB", levels[20]);
+    }
+
+    #[test]
+    fn blanker_attenuates_impulses_only() {
+        let mut blanker = Blanker::new(10);
+        for _ in 0..50 {
+            assert!(!blanker.blank(&mut vec![1.; 64]));
+        }
+
+        // a static crash 30 dB up is scaled back to the reference energy
+        let mut impulse = vec![magnitude(30.); 64];
+        impulse[7] = magnitude(40.);
+        assert!(blanker.blank(&mut impulse));
+        let energy = db((impulse.iter().map(|b| b * b).sum::<f32>() / 64.).sqrt());
+        assert!(energy.abs() < 0.1, "impulse left at {} dB", energy);
+        // keeping its shape
+        assert!((db(impulse[7]) - db(impulse[0]) - 10.).abs() < 0.01);
+
+        // the reference barely moved, so a normal column passes unchanged
+        let mut bins = vec![1.; 64];
+        assert!(!blanker.blank(&mut bins));
+        assert_eq!(bins, vec![1.; 64]);
+    }
+
+    #[test]
+    fn notch_removes_persistent_carriers() {
+        let mut notch = Notch::new(15, 40);
+        let mut notched = 0;
+        for i in 0..100 {
+            let mut bins = vec![1.; 64];
+            // a steady carrier, and a keyed signal sent half of the time
+            bins[20] = magnitude(30.);
+            if i % 4 < 2 {
+                bins[40] = magnitude(30.);
+            }
+            notched = notch.notch(&mut bins);
+
+            if i == 97 {
+                assert!(db(bins[20]).abs() < 1., "carrier left at {} dB", db(bins[20]));
+                assert!((db(bins[40]) - 30.).abs() < 0.01, "keyed signal at {} dB", db(bins[40]));
+            }
+        }
+        assert_eq!(notched, 1);
+    }
+
+    #[test]
+    fn history_keeps_latest_finished_frames() {
+        let start = Utc.timestamp_opt(1_609_502_400, 0).unwrap();
+        let column = |t: i64, level: f32| Column {
+            time:        start + Duration::seconds(t),
+            interval:    Duration::seconds(1),
+            sample_rate: 8000,
+            iq:          false,
+            total_bins:  64,
+            first_bin:   0,
+            bins:        vec![level; 64],
+        };
+
+        // 62.5 Hz per bin: bins 7 to 17 are kept, one beyond the range on either side
+        let mut history = History::new(60, 10, &[500, 1000]);
+        // columns sharing a pixel combine, keeping the strongest
+        history.push(column(1, 0.5));
+        history.push(column(2, 2.));
+        history.push(column(3, 1.));
+        let current = history.current().unwrap();
+        assert_eq!(current.columns.len(), 1);
+        assert_eq!(current.columns[0].first_bin, 7);
+        assert_eq!(current.columns[0].bins, vec![2.; 11]);
+
+        // one column in each of the next frames
+        let frames = MAX_FINISHED as i64 + 2;
+        for f in 1..=frames {
+            history.push(column(f * 60 + 30, 1.));
+        }
+        assert!(history.is_full());
+        assert_eq!(history.take_dropped(), 2);
+        assert_eq!(history.take_dropped(), 0);
+        let finished = history.take_finished();
+        assert_eq!(finished.len(), MAX_FINISHED);
+        assert!(!history.is_full());
+        for (i, frame) in finished.iter().enumerate() {
+            assert_eq!(frame.start, start + Duration::seconds(60 * (frames - MAX_FINISHED as i64 + i as i64)));
+            assert_eq!(frame.columns.len(), 1);
+        }
+        assert!(history.take_finished().is_empty());
+        assert_eq!(history.current().unwrap().start, start + Duration::seconds(60 * frames));
+
+        // a new layout finishes the frame early
+        history.configure(60, 20, &[500, 1000]);
+        assert!(history.current().is_none());
+        assert_eq!(history.take_finished().len(), 1);
+    }
+
+    #[test]
+    fn rows_outside_spectrum_are_empty() {
+        let axis = FreqAxis::new(1024, 8000, false);
+        let rows = axis.rows(&[-1000, 1000], 10);
+        assert!(rows[..5].iter().all(|r| *r != RowSample::Empty));
+        assert!(rows[6..].iter().all(|r| *r == RowSample::Empty));
+        assert_eq!(RowSample::Empty.sample(&[1.; 4], 0), 0.);
+    }
+}